@@ -3,8 +3,8 @@ MEMORY
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
   FLASH : ORIGIN = 0x08000000, LENGTH = 2048K
   RAM   : ORIGIN = 0x10000000, LENGTH = 64K      /* CPU coupled */
-  SRAM1 : ORIGIN = 0x20000000, LENGTH = 112K
-  SRAM2 : ORIGIN = 0x2001C000, LENGTH = 16K
+  /* SRAM1 (112K) and SRAM2 (16K) are contiguous, use them as one region */
+  SRAM1 : ORIGIN = 0x20000000, LENGTH = 128K
   SRAM3 : ORIGIN = 0x20020000, LENGTH = 64K
 }
 
@@ -31,12 +31,6 @@ SECTIONS
     . = ALIGN(4);
   } > SRAM1
 
-  .sram2bss (NOLOAD) : ALIGN(4)
-  {
-    *(.sram2bss);
-    . = ALIGN(4);
-  } > SRAM2
-
   .sram3bss (NOLOAD) : ALIGN(4)
   {
     *(.sram3bss);
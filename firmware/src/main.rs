@@ -14,7 +14,7 @@ use stm32f4xx_hal::timer::{Timer, Event};
 use stm32f4xx_hal::rcc::RccExt;
 use stm32f4xx_hal::serial::Serial;
 use stm32f4xx_hal::gpio::{PinState, Speed};
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
 use panic_halt as _;
 //use panic_semihosting as _;
@@ -26,8 +26,11 @@ mod spiflash;
 #[cfg(feature="test-mode")]
 mod test_mode;
 mod konami_mode;
+mod touch_calib;
 
-use display::interface::Action;
+use display::interface::{Action, TouchHandler as _, EEPROM_TOUCH_CALIB, EEPROM_BACKLIGHT};
+use display::backlight::BACKLIGHT_SETTINGS_LEN;
+use display::overlay::{OVERLAY_WIDTH, OVERLAY_HEIGHT, OVERLAY_POS, OVERLAY_ALPHA};
 use display::{WIDTH, HEIGHT, CHARW, CHARH};
 
 // Convenient type aliases for the instantiations of the generic library types
@@ -59,6 +62,9 @@ const FB_GRAPHICS_SIZE: usize = (WIDTH as usize) * (HEIGHT as usize);
 
 #[link_section = ".sram1bss"]
 static mut FB_GRAPHICS: [u8; FB_GRAPHICS_SIZE] = [0; FB_GRAPHICS_SIZE];
+// Second buffer for drawing frames off-screen
+#[link_section = ".sram1bss"]
+static mut FB_GRAPHICS_BACK: [u8; FB_GRAPHICS_SIZE] = [0; FB_GRAPHICS_SIZE];
 
 // Console framebuffer
 // Size includes one extra row for scrolling via DMA
@@ -66,16 +72,32 @@ const FB_CONSOLE_SIZE: usize = (WIDTH as usize) * ((HEIGHT + CHARH) as usize);
 #[link_section = ".sram3bss"]
 static mut FB_CONSOLE: [u8; FB_CONSOLE_SIZE] = [0; FB_CONSOLE_SIZE];
 
+// Storage for images uploaded by the host
+const IMAGE_BUF_SIZE: usize = 16 * 1024;
+static mut IMAGE_BUF: [u8; IMAGE_BUF_SIZE] = [0; IMAGE_BUF_SIZE];
+
 // Cursor framebuffer: just the cursor itself
 const CURSOR_COLOR: u8 = 127;
 static CURSORBUF: [u8; CHARW as usize] = [CURSOR_COLOR; CHARW as usize];
 static CURSOR_ENABLED: AtomicBool = AtomicBool::new(false);
+// Cursor position as (cx << 16) | cy
+static CURSOR_POS: AtomicU32 = AtomicU32::new(0);
+
+// Overlay framebuffer, shown in layer 2 instead of the cursor
+const OVERLAY_SIZE: usize = (OVERLAY_WIDTH as usize) * (OVERLAY_HEIGHT as usize);
+#[link_section = ".sram1bss"]
+static mut OVERLAY_BUF: [u8; OVERLAY_SIZE] = [0; OVERLAY_SIZE];
+static OVERLAY_SHOWN: AtomicBool = AtomicBool::new(false);
+
+// Set by the blink timer, which also serves as a clock for timeouts
+static TICK: AtomicBool = AtomicBool::new(false);
+const TICK_MS: u16 = 250;
 
 // UART receive buffer
 static mut UART_RX: Queue<u8, 1024> = Queue::new();
 
-// Touch event buffer
-static mut TOUCH_EVT: Queue<u16, 16> = Queue::new();
+// Touch event buffer: true for press or false for release, and raw position
+static mut TOUCH_EVT: Queue<(bool, (u16, u16)), 16> = Queue::new();
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
@@ -104,7 +126,11 @@ fn main() -> ! {
     let mut disp_on = gpioa.pa8.into_push_pull_output_in_state(PinState::Low);
 
     // LCD backlight enable
-    let _backlight = gpiod.pd12.into_push_pull_output_in_state(PinState::High);
+    // LCD backlight, dimmable via PWM: start at full brightness
+    let (_, (backlight, ..)) = peri.TIM4.pwm_hz(1.kHz(), &clocks);
+    let mut backlight = backlight.with(gpiod.pd12);
+    backlight.set_duty(backlight.get_max_duty());
+    backlight.enable();
 
     // Output pin connected to Boot0 + capacitor
     let bootpin = gpiob.pb7.into_push_pull_output_in_state(PinState::Low);
@@ -113,7 +139,7 @@ fn main() -> ! {
     let mut blink_timer = Timer::new(peri.TIM3, &clocks).counter_hz();
 
     // Set up touch detection timer
-    let mut touch_timer = Timer::new(peri.TIM4, &clocks).counter_hz();
+    let mut touch_timer = Timer::new(peri.TIM5, &clocks).counter_hz();
 
     // External Flash memory via SPI
     #[cfg(feature="test-mode")]
@@ -178,22 +204,20 @@ fn main() -> ! {
     gpioc.pc12.into_pull_down_input();
     gpioc.pc11.into_pull_down_input();
 
-    // Pins for touch screen
+    // Pins for touch screen: start by measuring the X axis, the TIM5 handler
+    // switches between the axes by reconfiguring them
     let _touch_yd = gpioc.pc0.into_pull_down_input();
-    let _touch_yu = gpioc.pc1.into_floating_input();  // LATER: analog
+    let _touch_yu = gpioc.pc1.into_analog();
     let _touch_xl = gpioc.pc2.into_push_pull_output_in_state(PinState::Low);
     let _touch_xr = gpioc.pc3.into_push_pull_output_in_state(PinState::High);
 
     // Pin for resetting the APU
     let mut reset_pin = gpioc.pc8.into_open_drain_output_in_state(PinState::High);
 
-    // Set yu input pin to analog mode.  Hardcoded for now!
-    modif!(GPIOC.moder: moder1 = @analog);
-
     // Activate and configure ADC.
     modif!(RCC.apb2enr: adc1en = true);
     pulse!(RCC.apb2rstr: adcrst);
-    // One conversion of channel 11, continuous mode.
+    // One conversion of channel 11 (yu), continuous mode.
     write!(ADC1.sqr1: l = 0);
     write!(ADC1.sqr3: sq1 = 11);
     write!(ADC1.cr1: awden = true);
@@ -247,26 +271,17 @@ fn main() -> ! {
     // Frame buffer number of lines
     write!(LTDC.layer1.cfblnr: cfblnbr = HEIGHT);
     // Set up 256-color LUT
+    // (layer 2 needs the same LUT for the overlay)
     for (i, (r, g, b)) in display::console::get_lut_colors().enumerate() {
         write!(LTDC.layer1.clutwr: clutadd = i as u8, red = r, green = g, blue = b);
+        write!(LTDC.layer2.clutwr: clutadd = i as u8, red = r, green = g, blue = b);
     }
 
-    // Configure layer 2 (cursor)
-
-    // Initial position: top left character
-    write!(LTDC.layer2.whpcr: whstpos = H_WIN_START + 1, whsppos = H_WIN_START + CHARW);
-    write!(LTDC.layer2.wvpcr: wvstpos = V_WIN_START + CHARH, wvsppos = V_WIN_START + CHARH);
-    write!(LTDC.layer2.pfcr: pf = 0b101);  // L-8 without CLUT
-    write!(LTDC.layer2.cacr: consta = 0xFF);
-    write!(LTDC.layer2.dccr: dcalpha = 0, dcred = 0, dcgreen = 0, dcblue = 0);
-    write!(LTDC.layer2.bfcr: bf1 = 6, bf2 = 7);  // Constant alpha * Pixel alpha
-    write!(LTDC.layer2.cfbar: cfbadd = CURSORBUF.as_ptr() as u32);
-    write!(LTDC.layer2.cfblr: cfbll = CHARW + 3, cfbp = CHARW);
-    write!(LTDC.layer2.cfblnr: cfblnbr = 1);  // Cursor is one line of 6 pixels
+    // Configure layer 2 (cursor, initially disabled)
+    configure_cursor_layer();
 
-    // Enable layer1, disable layer2 initially
+    // Enable layer1
     modif!(LTDC.layer1.cr: cluten = true, len = true);
-    modif!(LTDC.layer2.cr: len = false);
 
     // Reload config (immediate)
     write!(LTDC.srcr: imr = true);
@@ -283,12 +298,12 @@ fn main() -> ! {
     // Enable interrupts
     unsafe {
         NVIC::unmask(pac::Interrupt::TIM3);
-        NVIC::unmask(pac::Interrupt::TIM4);
+        NVIC::unmask(pac::Interrupt::TIM5);
     }
     blink_timer.listen(Event::Update);
     blink_timer.start(4.Hz()).unwrap();
     touch_timer.listen(Event::Update);
-    touch_timer.start(100.Hz()).unwrap();
+    touch_timer.start(200.Hz()).unwrap();
 
     let fbimpls = FbImpl { width: WIDTH, has_cursor: true };
     let console = display::console::Console::new(
@@ -297,10 +312,15 @@ fn main() -> ! {
         position_cursor as fn(_, _)
     );
     let fbimpls = FbImpl { width: WIDTH, has_cursor: false };
+    let mut graphics = FrameBuffer::new(unsafe { &mut FB_GRAPHICS[..] }, WIDTH, HEIGHT, fbimpls);
+    graphics.set_back_buffer(unsafe { &mut FB_GRAPHICS_BACK[..] });
+    let fbimpls = FbImpl { width: OVERLAY_WIDTH, has_cursor: false };
     let mut disp = display::interface::DisplayState::new(
-        FrameBuffer::new(unsafe { &mut FB_GRAPHICS[..] }, WIDTH, HEIGHT, fbimpls),
+        graphics,
         console,
-        TouchHandler { calib: (6, 150, 1, 0) }
+        TouchHandler { calib: (6, 150, 10, 85) },
+        display::image::ImageStore::new(unsafe { &mut IMAGE_BUF[..] }),
+        display::overlay::Overlay::new(unsafe { &mut OVERLAY_BUF[..] }, fbimpls)
     );
 
     // Make sure the USART receive event flag is clear
@@ -310,6 +330,25 @@ fn main() -> ! {
     let mut uart = unsafe { UART_RX.split().1 };
     let mut touch = unsafe { TOUCH_EVT.split().1 };
 
+    // Load the touch calibration from EEPROM; without one, the default is kept
+    // until the host requests a calibration
+    let (calib_len_addr, calib_data_addr) = EEPROM_TOUCH_CALIB;
+    let mut calib_buf = [0; 4];
+    if let Ok(&[xd, xo, yd, yo]) = eeprom.read_stored_entry(calib_len_addr, calib_data_addr,
+                                                             &mut calib_buf) {
+        // a zero divisor would make every touch panic
+        if xd > 0 && yd > 0 {
+            disp.split().2.set_calib((xd as u16, xo as u16, yd as u16, yo as u16));
+        }
+    }
+
+    // Load the backlight settings from EEPROM
+    let mut backlight_buf = [0; BACKLIGHT_SETTINGS_LEN];
+    if let Ok(settings) = eeprom.read_stored_entry(EEPROM_BACKLIGHT.0, EEPROM_BACKLIGHT.1,
+                                                   &mut backlight_buf) {
+        disp.configure_backlight(settings);
+    }
+
     #[cfg(feature="test-mode")]
     {
         use display::{VER_MAJOR, VER_MINOR};
@@ -342,15 +381,36 @@ fn main() -> ! {
 
     let mut touch_ring = wheelbuf::WheelBuf::new([0u16; 8]);
 
+    let mut set_backlight = |level: u8| {
+        let duty = backlight.get_max_duty() as u32 * level as u32 / 255;
+        backlight.set_duty(duty as u16);
+    };
+    let mut blank_display = |blank: bool| disp_on.set_state(PinState::from(!blank));
+
     // Normal main loop: process input from UART
     loop {
-        if let Some(ev) = touch.dequeue() {
-            let (x, _) = disp.process_touch(ev);
-            touch_ring.push(x / 120);
-            if touch_ring.iter().eq(konami_mode::ACTIVATION) {
-                konami_mode::run(&mut disp, &mut reset_pin, None);
+        if TICK.swap(false, Ordering::Relaxed) {
+            match disp.tick(TICK_MS) {
+                Action::HideOverlay => hide_overlay(),
+                // palette animation, driven by the blink timer
+                Action::UpdateLut(updates) => write_lut(updates.iter().cloned()),
+                Action::SetBacklight(level) => set_backlight(level),
+                // screen saver
+                Action::BlankDisplay(blank) => blank_display(blank),
+                _ => (),
             }
         }
+        match touch.dequeue() {
+            Some((true, ev)) => {
+                let (x, _) = disp.process_touch(ev);
+                touch_ring.push(x / 120);
+                if touch_ring.iter().eq(konami_mode::ACTIVATION) {
+                    konami_mode::run(&mut disp, &mut reset_pin, None);
+                }
+            }
+            Some((false, ev)) => disp.process_release(ev),
+            None => (),
+        }
         if let Some(ch) = uart.dequeue() {
             match disp.process_byte(ch) {
                 Action::None => (),
@@ -361,6 +421,18 @@ fn main() -> ! {
                 Action::WriteEeprom(len_addr, data_addr, data) => {
                     let _ = eeprom.write_stored_entry(len_addr, data_addr, data);
                 }
+                Action::CalibrateTouch => if let Some(calib) = touch_calib::run(&mut disp) {
+                    let _ = eeprom.write_stored_entry(calib_len_addr, calib_data_addr, &calib);
+                },
+                Action::SetLut(start, colors) => {
+                    write_lut((start..=255).zip(colors.chunks(3).map(|c| (c[0], c[1], c[2]))));
+                }
+                Action::ResetLut => write_lut((0..=255).zip(display::console::get_lut_colors())),
+                Action::UpdateLut(updates) => write_lut(updates.iter().cloned()),
+                Action::SetBacklight(level) => set_backlight(level),
+                Action::BlankDisplay(blank) => blank_display(blank),
+                Action::ShowOverlay(buf) => show_overlay(buf),
+                Action::HideOverlay => hide_overlay(),
             }
         }
     }
@@ -386,6 +458,11 @@ pub fn enable_cursor(en: bool) {
 }
 
 fn position_cursor(cx: u16, cy: u16) {
+    CURSOR_POS.store((cx as u32) << 16 | cy as u32, Ordering::Relaxed);
+    // while the overlay is shown, the position is applied when it is hidden
+    if OVERLAY_SHOWN.load(Ordering::Relaxed) {
+        return;
+    }
     write!(LTDC.layer2.whpcr: whstpos = H_WIN_START + cx*CHARW + 1,
            whsppos = H_WIN_START + (cx + 1)*CHARW);
     write!(LTDC.layer2.wvpcr: wvstpos = V_WIN_START + (cy + 1)*CHARH,
@@ -394,12 +471,71 @@ fn position_cursor(cx: u16, cy: u16) {
     write!(LTDC.srcr: vbr = true);
 }
 
+/// Set up layer 2 to show the cursor, which is disabled until the next blink.
+fn configure_cursor_layer() {
+    write!(LTDC.layer2.pfcr: pf = 0b101);  // L-8 without CLUT
+    write!(LTDC.layer2.cacr: consta = 0xFF);
+    write!(LTDC.layer2.dccr: dcalpha = 0, dcred = 0, dcgreen = 0, dcblue = 0);
+    write!(LTDC.layer2.bfcr: bf1 = 6, bf2 = 7);  // Constant alpha * Pixel alpha
+    write!(LTDC.layer2.cfbar: cfbadd = CURSORBUF.as_ptr() as u32);
+    write!(LTDC.layer2.cfblr: cfbll = CHARW + 3, cfbp = CHARW);
+    write!(LTDC.layer2.cfblnr: cfblnbr = 1);  // Cursor is one line of 6 pixels
+    modif!(LTDC.layer2.cr: cluten = false, len = false);
+    let pos = CURSOR_POS.load(Ordering::Relaxed);
+    position_cursor((pos >> 16) as u16, pos as u16);
+}
+
+/// Write (index, color) entries to the CLUT of both layers.
+fn write_lut(entries: impl Iterator<Item=(u8, (u8, u8, u8))>) {
+    // the CLUT should only be written during vertical blanking
+    wait_for!(LTDC.cdsr: vdes);
+    wait_for!(LTDC.cdsr: !vdes);
+    for (i, (r, g, b)) in entries {
+        write!(LTDC.layer1.clutwr: clutadd = i, red = r, green = g, blue = b);
+        write!(LTDC.layer2.clutwr: clutadd = i, red = r, green = g, blue = b);
+    }
+}
+
+/// Show the overlay window in layer 2, replacing the cursor.
+fn show_overlay(buf: &[u8]) {
+    // don't let the blink timer reload a half-done configuration
+    interrupts::free(|_| {
+        OVERLAY_SHOWN.store(true, Ordering::Relaxed);
+        let (x, y) = OVERLAY_POS;
+        write!(LTDC.layer2.whpcr: whstpos = H_WIN_START + x + 1,
+               whsppos = H_WIN_START + x + OVERLAY_WIDTH);
+        write!(LTDC.layer2.wvpcr: wvstpos = V_WIN_START + y + 1,
+               wvsppos = V_WIN_START + y + OVERLAY_HEIGHT);
+        write!(LTDC.layer2.pfcr: pf = 0b101);  // L-8 with CLUT
+        write!(LTDC.layer2.cacr: consta = OVERLAY_ALPHA);
+        write!(LTDC.layer2.bfcr: bf1 = 4, bf2 = 5);  // Constant alpha
+        write!(LTDC.layer2.cfbar: cfbadd = buf.as_ptr() as u32);
+        write!(LTDC.layer2.cfblr: cfbll = OVERLAY_WIDTH + 3, cfbp = OVERLAY_WIDTH);
+        write!(LTDC.layer2.cfblnr: cfblnbr = OVERLAY_HEIGHT);
+        modif!(LTDC.layer2.cr: cluten = true, len = true);
+        // reload on next vsync
+        write!(LTDC.srcr: vbr = true);
+    });
+}
+
+/// Hide the overlay window and go back to showing the cursor in layer 2.
+fn hide_overlay() {
+    // don't let the blink timer reload a half-done configuration
+    interrupts::free(|_| {
+        OVERLAY_SHOWN.store(false, Ordering::Relaxed);
+        configure_cursor_layer();
+    });
+}
+
 #[interrupt]
 fn TIM3() {
     static mut VISIBLE: bool = false;
-    // Toggle layer2 on next vsync
+    TICK.store(true, Ordering::Relaxed);
+    // Toggle layer2 on next vsync, unless it shows the overlay
     *VISIBLE = !*VISIBLE;
-    modif!(LTDC.layer2.cr: len = bit(CURSOR_ENABLED.load(Ordering::Relaxed) && *VISIBLE));
+    if !OVERLAY_SHOWN.load(Ordering::Relaxed) {
+        modif!(LTDC.layer2.cr: len = bit(CURSOR_ENABLED.load(Ordering::Relaxed) && *VISIBLE));
+    }
     write!(LTDC.srcr: vbr = true);
     // Reset timer
     modif!(TIM3.sr: uif = false);
@@ -417,27 +553,66 @@ const NSAMPLES: usize = 8;
 
 struct TouchState {
     last: bool,
-    data: [u16; NSAMPLES],
+    // last position measured while touched, reported on release
+    pos: (u16, u16),
+    // axis currently being measured, 0 = X, 1 = Y
+    axis: usize,
+    data: [[u16; NSAMPLES]; 2],
     idx: usize,
 }
 
+/// Configure the touch plates and ADC to measure the given axis.
+///
+/// One plate is driven from low to high, while the other plate is connected
+/// to the ADC and pulled down at its other end, so that it reads near zero
+/// while the screen is not touched.
+fn select_touch_axis(axis: usize) {
+    if axis == 0 {
+        // release the Y plate before driving the X plate
+        modif!(GPIOC.moder: moder0 = @input, moder1 = @analog);
+        modif!(GPIOC.pupdr: pupdr0 = @pull_down);
+        write!(GPIOC.bsrr: br2 = true, bs3 = true);
+        modif!(GPIOC.pupdr: pupdr2 = @floating);
+        modif!(GPIOC.moder: moder2 = @output, moder3 = @output);
+        write!(ADC1.sqr3: sq1 = 11);
+    } else {
+        modif!(GPIOC.moder: moder2 = @input, moder3 = @analog);
+        modif!(GPIOC.pupdr: pupdr2 = @pull_down);
+        write!(GPIOC.bsrr: bs0 = true, br1 = true);
+        modif!(GPIOC.pupdr: pupdr0 = @floating);
+        modif!(GPIOC.moder: moder0 = @output, moder1 = @output);
+        write!(ADC1.sqr3: sq1 = 13);
+    }
+    modif!(ADC1.cr2: swstart = true);
+}
+
 #[interrupt]
-fn TIM4() {
-    static mut STATE: TouchState = TouchState { last: false, data: [0; NSAMPLES], idx: 0};
+fn TIM5() {
+    static mut STATE: TouchState = TouchState { last: false, pos: (0, 0), axis: 0,
+                                                data: [[0; NSAMPLES]; 2], idx: 0 };
+    // the plates have settled since the last interrupt
     let data = read!(ADC1.dr: data);
-    STATE.data[STATE.idx] = data;
-    STATE.idx = (STATE.idx + 1) % NSAMPLES;
-    let mini = STATE.data.iter().cloned().min().unwrap();
-    if !STATE.last && mini > THRESHOLD {
-        let mean = STATE.data.iter().map(|&v| v as u32).sum::<u32>() / NSAMPLES as u32;
-        unsafe { let _ = TOUCH_EVT.split().0.enqueue(mean as u16); }
-        STATE.last = true;
-    } else if STATE.last && mini < THRESHOLD {
-        STATE.last = false;
+    STATE.data[STATE.axis][STATE.idx] = data;
+    if STATE.axis == 1 {
+        STATE.idx = (STATE.idx + 1) % NSAMPLES;
+        let mini = STATE.data.iter().flatten().cloned().min().unwrap();
+        if mini > THRESHOLD {
+            let mean = |d: &[u16]| (d.iter().map(|&v| v as u32).sum::<u32>() / NSAMPLES as u32) as u16;
+            STATE.pos = (mean(&STATE.data[0]), mean(&STATE.data[1]));
+            if !STATE.last {
+                unsafe { let _ = TOUCH_EVT.split().0.enqueue((true, STATE.pos)); }
+                STATE.last = true;
+            }
+        } else if STATE.last && mini < THRESHOLD {
+            unsafe { let _ = TOUCH_EVT.split().0.enqueue((false, STATE.pos)); }
+            STATE.last = false;
+        }
     }
+    STATE.axis = 1 - STATE.axis;
+    select_touch_axis(STATE.axis);
     // Reset timer
-    modif!(TIM4.sr: uif = false);
-    modif!(TIM4.cr1: cen = true);
+    modif!(TIM5.sr: uif = false);
+    modif!(TIM5.cr1: cen = true);
 }
 
 #[cortex_m_rt::exception]
@@ -490,21 +665,22 @@ pub struct TouchHandler {
 }
 
 impl display::interface::TouchHandler for TouchHandler {
-    type Event = u16;
+    type Event = (u16, u16);
 
     fn wait(&self) -> (u16, u16) {
         let mut touch = unsafe { TOUCH_EVT.split().1 };
         loop {
-            if let Some(ev) = touch.dequeue() {
+            if let Some((true, ev)) = touch.dequeue() {
                 return self.convert(ev);
             }
             asm::wfi();
         }
     }
 
-    fn convert(&self, ev: u16) -> (u16, u16) {
-        let x = (ev / self.calib.0) - self.calib.1;
-        (x, 0)
+    fn convert(&self, (ev_x, ev_y): (u16, u16)) -> (u16, u16) {
+        let x = (ev_x / self.calib.0).saturating_sub(self.calib.1);
+        let y = (ev_y / self.calib.2).saturating_sub(self.calib.3);
+        (x.min(WIDTH - 1), y.min(HEIGHT - 1))
     }
 
     fn set_calib(&mut self, calib: (u16, u16, u16, u16)) {
@@ -568,4 +744,9 @@ impl display::framebuf::FbImpl for FbImpl {
         write!(LTDC.srcr: vbr = true);
         enable_cursor(self.has_cursor);
     }
+
+    fn wait_for_reload(&self) {
+        // the reload bit is cleared by hardware on vsync
+        wait_for!(LTDC.srcr: !vbr);
+    }
 }
@@ -0,0 +1,108 @@
+//! Interactive calibration of the touch screen.
+
+use core::sync::atomic::Ordering;
+use cortex_m::asm;
+use display::interface::{Palette, TouchHandler as _};
+use display::framebuf::{MEDIUMFONT as FONT, Align, BLACK_ON_WHITE, RED_ON_WHITE};
+use display::{WIDTH, HEIGHT};
+use crate::{DisplayState, FrameBuffer, TOUCH_EVT, TICK, TICK_MS};
+
+/// Positions of the targets, near opposite corners of the display.
+const TARGETS: [(u16, u16); 2] = [(40, 24), (WIDTH - 41, HEIGHT - 25)];
+
+/// Time to wait for each touch before giving up, in milliseconds.
+const TIMEOUT_MS: u32 = 30_000;
+
+/// Run the calibration and apply the result.
+///
+/// The targets are drawn into the graphics back buffer, ignoring the clip
+/// rectangle, so that the host's graphics are shown unchanged afterwards.
+///
+/// Returns the new calibration in the format of the TOUCH_CALIB command, or
+/// None if the screen was not touched in time.
+pub fn run(disp: &mut DisplayState) -> Option<[u8; 4]> {
+    let was_gfx = disp.is_graphics();
+    let (gfx, con, touch) = disp.split();
+
+    let swapped = gfx.swap_back_buffer();
+    gfx.set_clip((0, 0), (WIDTH - 1, HEIGHT - 1));
+    gfx.activate();
+
+    let mut msg: (&[u8], &Palette) = (b"Touch the center of each cross", BLACK_ON_WHITE);
+    let calib = loop {
+        let Some(raw) = measure(gfx, msg) else { break None };
+        let x = axis_calib(raw[0].0, raw[1].0, TARGETS[0].0, TARGETS[1].0);
+        let y = axis_calib(raw[0].1, raw[1].1, TARGETS[0].1, TARGETS[1].1);
+        if let (Some((xd, xo)), Some((yd, yo))) = (x, y) {
+            break Some([xd, xo, yd, yo]);
+        }
+        msg = (b"Calibration failed, please try again", RED_ON_WHITE);
+    };
+
+    if let Some([xd, xo, yd, yo]) = calib {
+        touch.set_calib((xd as u16, xo as u16, yd as u16, yo as u16));
+    }
+
+    if swapped {
+        gfx.swap_back_buffer();
+    } else {
+        gfx.clear(0);
+    }
+    if was_gfx {
+        gfx.activate();
+    } else {
+        con.activate();
+    }
+    disp.restore_clip();
+    calib
+}
+
+/// Show the targets one after the other and return the raw touch events.
+fn measure(gfx: &mut FrameBuffer, (text, pal): (&[u8], &Palette)) -> Option<[(u16, u16); 2]> {
+    // discard touches from before the calibration
+    let mut touch = unsafe { TOUCH_EVT.split().1 };
+    while touch.dequeue().is_some() {}
+
+    let mut raw = [(0, 0); 2];
+    for (ev, &(x, y)) in raw.iter_mut().zip(&TARGETS) {
+        gfx.clear(15);
+        gfx.text_aligned(FONT, 0, (HEIGHT - FONT.size().1) / 2, text, pal, Align::Center(WIDTH));
+        gfx.line(x - 10, y, x + 10, y, 0);
+        gfx.line(x, y - 10, x, y + 10, 0);
+        gfx.circle(x, y, 5, 0);
+        *ev = wait_raw()?;
+    }
+    Some(raw)
+}
+
+/// Wait for a touch press event, with timeout.
+fn wait_raw() -> Option<(u16, u16)> {
+    let mut touch = unsafe { TOUCH_EVT.split().1 };
+    let mut elapsed = 0;
+    while elapsed < TIMEOUT_MS {
+        if let Some((true, ev)) = touch.dequeue() {
+            return Some(ev);
+        }
+        if TICK.swap(false, Ordering::Relaxed) {
+            elapsed += TICK_MS as u32;
+        }
+        asm::wfi();
+    }
+    None
+}
+
+/// Calculate divisor and offset for one axis from the raw values `r1`, `r2`
+/// measured at the pixel positions `p1`, `p2`.
+///
+/// The touch handler calculates the position as `raw / divisor - offset`.
+fn axis_calib(r1: u16, r2: u16, p1: u16, p2: u16) -> Option<(u8, u8)> {
+    if r2 <= r1 {
+        return None;
+    }
+    let div = (r2 - r1 + (p2 - p1) / 2) / (p2 - p1);
+    if div == 0 {
+        return None;
+    }
+    let offset = (r1 / div).checked_sub(p1)?;
+    Some((u8::try_from(div).ok()?, u8::try_from(offset).ok()?))
+}
@@ -0,0 +1,64 @@
+//! Backlight brightness, with automatic dimming after a period of inactivity.
+
+/// Length of the backlight settings, as sent by the host and stored in the
+/// EEPROM: brightness, dimmed brightness, and idle timeout in seconds (u16).
+pub const BACKLIGHT_SETTINGS_LEN: usize = 4;
+
+pub struct Backlight {
+    // brightness while active, and after the idle timeout
+    level: u8,
+    dim_level: u8,
+    // time without activity before dimming, in milliseconds, or 0
+    timeout: u32,
+    idle: u32,
+    dimmed: bool,
+    // if true, the brightness has changed and was not applied yet
+    changed: bool,
+}
+
+impl Default for Backlight {
+    fn default() -> Self {
+        Self { level: 255, dim_level: 255, timeout: 0, idle: 0, dimmed: false, changed: false }
+    }
+}
+
+impl Backlight {
+    /// Apply new settings, in the format described for
+    /// `BACKLIGHT_SETTINGS_LEN`.
+    pub fn configure(&mut self, settings: &[u8]) {
+        if let &[level, dim_level, t0, t1, ..] = settings {
+            self.level = level;
+            self.dim_level = dim_level;
+            self.timeout = u16::from_be_bytes([t0, t1]) as u32 * 1000;
+            self.idle = 0;
+            self.dimmed = false;
+            self.changed = true;
+        }
+    }
+
+    /// Register activity, which restores full brightness.  Returns true if
+    /// the backlight was dimmed.
+    pub fn activity(&mut self) -> bool {
+        self.idle = 0;
+        let was_dimmed = core::mem::replace(&mut self.dimmed, false);
+        self.changed |= was_dimmed;
+        was_dimmed
+    }
+
+    /// Advance the idle time by the given number of milliseconds.
+    pub fn tick(&mut self, ms: u16) {
+        if self.timeout > 0 && !self.dimmed {
+            self.idle = self.idle.saturating_add(ms as u32);
+            if self.idle >= self.timeout {
+                self.dimmed = true;
+                self.changed = true;
+            }
+        }
+    }
+
+    /// Return the brightness to set, if it has changed since the last call.
+    pub fn take_change(&mut self) -> Option<u8> {
+        core::mem::replace(&mut self.changed, false)
+            .then_some(if self.dimmed { self.dim_level } else { self.level })
+    }
+}
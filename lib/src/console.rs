@@ -10,8 +10,8 @@ const DEFAULT_COLOR: u8 = 7;
 const DEFAULT_BKGRD: u8 = 0;
 
 /// Number of characters in the visible screen.
-const COLS: u16 = WIDTH / CHARW;
-const ROWS: u16 = HEIGHT / CHARH;
+pub const COLS: u16 = WIDTH / CHARW;
+pub const ROWS: u16 = HEIGHT / CHARH;
 
 const HEX: &[u8] = b"0123456789ABCDEF";
 
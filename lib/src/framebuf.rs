@@ -29,6 +29,11 @@ impl Font {
         (self.charw, self.charh)
     }
 
+    /// Return the pixel size of the given text in this font.
+    pub fn text_size(&self, text: &[u8]) -> (u16, u16) {
+        ((text.len() as u16).saturating_mul(self.charw), self.charh)
+    }
+
     fn data(&self, chr: u8) -> &[u8] {
         &self.data[self.chars[chr as usize]..]
     }
@@ -47,6 +52,71 @@ pub const FONTS: &[Font] = &[
 pub const CONSOLEFONT: &Font = &FONTS[0];
 pub const MEDIUMFONT: &Font = &FONTS[1];
 
+/// Horizontal alignment of text within a given width.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center(u16),
+    Right(u16),
+}
+
+impl Align {
+    /// Return the same alignment, but within the given width.
+    pub fn with_width(self, width: u16) -> Self {
+        match self {
+            Align::Left => Align::Left,
+            Align::Center(_) => Align::Center(width),
+            Align::Right(_) => Align::Right(width),
+        }
+    }
+
+    /// Return the horizontal offset for a text of the given pixel width.
+    pub fn offset(self, textw: u16) -> u16 {
+        match self {
+            Align::Left => 0,
+            Align::Center(width) => width.saturating_sub(textw) / 2,
+            Align::Right(width) => width.saturating_sub(textw),
+        }
+    }
+}
+
+/// Characters to append to text that was truncated.
+const ELLIPSIS: &[u8] = b"...";
+
+/// Iterator over the lines of a text wrapped to a given number of columns.
+///
+/// Lines are broken at spaces where possible, and at newline characters.
+struct WrapLines<'a> {
+    text: &'a [u8],
+    cols: usize,
+}
+
+impl<'a> Iterator for WrapLines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.text.is_empty() || self.cols == 0 {
+            return None;
+        }
+        let text = self.text;
+        let para_end = text.iter().position(|&ch| ch == b'\n').unwrap_or(text.len());
+        if para_end <= self.cols {
+            self.text = &text[(para_end + 1).min(text.len())..];
+            return Some(&text[..para_end]);
+        }
+        let (line, rest) = match text[..=self.cols].iter().rposition(|&ch| ch == b' ') {
+            Some(brk) if brk > 0 => (&text[..brk], &text[brk..]),
+            // no space to break at: break within the word
+            _ => (&text[..self.cols], &text[self.cols..]),
+        };
+        // spaces at the line break are dropped, including a following newline
+        let rest = &rest[rest.iter().position(|&ch| ch != b' ').unwrap_or(rest.len())..];
+        self.text = rest.strip_prefix(b"\n").unwrap_or(rest);
+        Some(line)
+    }
+}
+
 /// Abstraction for different implementations of framebuffers, which can use
 /// e.g. hardware acceleration for certain operations.
 pub trait FbImpl {
@@ -57,6 +127,9 @@ pub trait FbImpl {
     fn copy_rect(&mut self, buf: &mut [u8], x1: u16, y1: u16, x2: u16, y2: u16, nx: u16, ny: u16);
     /// Activate this framebuffer.
     fn activate(&self, buf: &mut [u8]);
+    /// Wait until a previous activation has taken effect, if it is delayed
+    /// (e.g. until the next vertical sync).
+    fn wait_for_reload(&self) {}
 }
 
 /// Represents a single 8-bit framebuffer.
@@ -68,18 +141,76 @@ pub struct FrameBuffer<'buf, Fb> {
     clip1: (u16, u16),
     // Bottom-right pixel in clip rectangle (inclusive)
     clip2: (u16, u16),
+    // Off-screen buffer for drawing whole frames
+    back: Option<&'buf mut [u8]>,
+    // If true, a frame is being drawn; `buf` is then the off-screen buffer
+    // and `back` is displayed
+    in_frame: bool,
     impls: Fb,
 }
 
 impl<'buf, Fb: FbImpl> FrameBuffer<'buf, Fb> {
     pub fn new(buf: &'buf mut [u8], width: u16, height: u16, impls: Fb) -> Self {
-        Self { buf, width, height, impls, clip1: (0, 0), clip2: (width - 1, height - 1) }
+        Self { buf, width, height, impls, clip1: (0, 0), clip2: (width - 1, height - 1),
+               back: None, in_frame: false }
+    }
+
+    /// Set a second buffer of the same size, which is used to draw frames
+    /// off-screen.
+    pub fn set_back_buffer(&mut self, back: &'buf mut [u8]) {
+        self.back = Some(back);
+    }
+
+    /// Return true if a back buffer is set, so that frames can be drawn
+    /// off-screen.
+    pub fn has_back_buffer(&self) -> bool {
+        self.back.is_some()
     }
 
+    /// Return the buffer that is drawn to.
     pub fn buf(&self) -> &[u8] {
         self.buf
     }
 
+    /// Return the buffer that is displayed when this framebuffer is active.
+    pub fn front_buf(&self) -> &[u8] {
+        match self.back {
+            Some(ref front) if self.in_frame => front,
+            _ => self.buf,
+        }
+    }
+
+    /// Start drawing a frame off-screen, starting from the current contents.
+    /// Without a back buffer, drawing continues on-screen.
+    pub fn begin_frame(&mut self) {
+        if let (false, Some(back)) = (self.in_frame, self.back.as_mut()) {
+            // the back buffer may still be displayed until the last frame
+            // has been activated
+            self.impls.wait_for_reload();
+            back.copy_from_slice(self.buf);
+            core::mem::swap(&mut self.buf, back);
+            self.in_frame = true;
+        }
+    }
+
+    /// Finish drawing a frame off-screen.  It is displayed with the next call
+    /// to `activate`.  Returns false if no frame was being drawn.
+    pub fn end_frame(&mut self) -> bool {
+        core::mem::replace(&mut self.in_frame, false)
+    }
+
+    /// Exchange the drawing buffer with the back buffer, without copying.
+    /// Returns false if there is no back buffer or a frame is being drawn.
+    pub fn swap_back_buffer(&mut self) -> bool {
+        if let (false, Some(back)) = (self.in_frame, self.back.as_mut()) {
+            self.impls.wait_for_reload();
+            core::mem::swap(&mut self.buf, back);
+            true
+        } else {
+            false
+        }
+    }
+
     #[inline(always)]
     pub fn set_pixel(&mut self, x: u16, y: u16, color: u8) {
         if self.clip1.0 <= x && x <= self.clip2.0 && self.clip1.1 <= y && y <= self.clip2.1 {
@@ -110,11 +241,51 @@ impl<'buf, Fb: FbImpl> FrameBuffer<'buf, Fb> {
     pub fn text(&mut self, font: &Font, mut px: u16, py: u16, text: &[u8], pal: &Palette) {
         let size = font.size();
         for &chr in text {
-            self.image(px, py, font.data(chr), size, pal);
-            px += size.0;
             if px >= self.width {
                 return;
             }
+            self.image(px, py, font.data(chr), size, pal);
+            px += size.0;
+        }
+    }
+
+    /// Draw text with the given font, aligned relative to (px, py).
+    pub fn text_aligned(&mut self, font: &Font, px: u16, py: u16, text: &[u8], pal: &Palette,
+                        align: Align) {
+        let px = px.saturating_add(align.offset(font.text_size(text).0));
+        self.text(font, px, py, text, pal);
+    }
+
+    /// Draw text with the given font into the box with (inclusive) coordinates
+    /// (x1, y1) to (x2, y2), after clearing it with the palette's background.
+    ///
+    /// The text is wrapped at spaces and newlines, and each line aligned within
+    /// the box width.  If the text doesn't fit, it is cut off after the last
+    /// line, optionally with an ellipsis.
+    #[allow(clippy::too_many_arguments)]
+    pub fn text_box(&mut self, font: &Font, x1: u16, y1: u16, x2: u16, y2: u16, text: &[u8],
+                    pal: &Palette, align: Align, ellipsis: bool) {
+        self.rect(x1, y1, x2, y2, pal[0]);
+        let (charw, charh) = font.size();
+        let width = 1 + x2.max(x1) - x1;
+        let align = align.with_width(width);
+        let cols = (width / charw) as usize;
+        let rows = ((1 + y2.max(y1) - y1) / charh) as usize;
+        let mut lines = WrapLines { text, cols }.peekable();
+        let mut py = y1;
+        for row in 0..rows {
+            let Some(mut line) = lines.next() else { return };
+            if ellipsis && row == rows - 1 && lines.peek().is_some() && cols >= ELLIPSIS.len() {
+                // last line with more to come: make room for the ellipsis
+                line = &line[..line.len().min(cols - ELLIPSIS.len())];
+                let linew = font.text_size(line).0 + font.text_size(ELLIPSIS).0;
+                let px = x1 + align.offset(linew);
+                self.text(font, px, py, line, pal);
+                self.text(font, px + font.text_size(line).0, py, ELLIPSIS, pal);
+            } else {
+                self.text_aligned(font, x1, py, line, pal, align);
+            }
+            py += charh;
         }
     }
 
@@ -168,6 +339,193 @@ impl<'buf, Fb: FbImpl> FrameBuffer<'buf, Fb> {
         self.impls.fill_rect(self.buf.as_mut(), x1, y1, x2, y2, color);
     }
 
+    /// Draw a filled rectangle with (inclusive) coordinates (x1, y1) to (x2, y2)
+    /// and corners rounded with radius r.
+    pub fn round_rect(&mut self, x1: u16, y1: u16, x2: u16, y2: u16, r: u16, color: u8) {
+        let (x1, y1, x2, y2) = (x1 as i32, y1 as i32, x2.max(x1) as i32, y2.max(y1) as i32);
+        let r = (r as i32).min((x2 - x1) / 2).min((y2 - y1) / 2);
+        for i in 0..r {
+            let inset = corner_inset(r, i);
+            self.hline(x1 + inset, x2 - inset, y1 + i, color);
+            self.hline(x1 + inset, x2 - inset, y2 - i, color);
+        }
+        if y2 - r >= y1 + r {
+            self.rect(x1 as u16, (y1 + r) as u16, x2 as u16, (y2 - r) as u16, color);
+        }
+    }
+
+    /// Draw a rectangle outline with (inclusive) coordinates (x1, y1) to (x2, y2),
+    /// corners rounded with radius r, and a border thickness of t pixels.
+    #[allow(clippy::too_many_arguments)]
+    pub fn round_rect_outline(&mut self, x1: u16, y1: u16, x2: u16, y2: u16, r: u16, t: u16,
+                              color: u8) {
+        let (x1, y1, x2, y2) = (x1 as i32, y1 as i32, x2.max(x1) as i32, y2.max(y1) as i32);
+        let r = (r as i32).min((x2 - x1) / 2).min((y2 - y1) / 2);
+        let t = t.max(1) as i32;
+        // the inner edge of the border is again a rounded rectangle
+        let (ix1, iy1, ix2, iy2) = (x1 + t, y1 + t, x2 - t, y2 - t);
+        if ix1 > ix2 || iy1 > iy2 {
+            return self.round_rect(x1 as u16, y1 as u16, x2 as u16, y2 as u16, r as u16, color);
+        }
+        let ir = (r - t).max(0);
+        for y in y1..=y2 {
+            let outer = corner_inset(r, (y - y1).min(y2 - y));
+            if y < iy1 || y > iy2 {
+                self.hline(x1 + outer, x2 - outer, y, color);
+            } else {
+                let inner = corner_inset(ir, (y - iy1).min(iy2 - y));
+                self.hline(x1 + outer, ix1 + inner - 1, y, color);
+                self.hline(ix2 - inner + 1, x2 - outer, y, color);
+            }
+        }
+    }
+
+    /// Set a pixel given by signed coordinates, which may be off-screen.
+    fn set_pixel_signed(&mut self, x: i32, y: i32, color: u8) {
+        if x >= 0 && y >= 0 && x <= u16::MAX as i32 && y <= u16::MAX as i32 {
+            self.set_pixel(x as u16, y as u16, color);
+        }
+    }
+
+    /// Draw a horizontal span between (inclusive) x1 and x2 at row y.  The
+    /// coordinates may be off-screen, the span is clipped to the clip rectangle.
+    fn hline(&mut self, x1: i32, x2: i32, y: i32, color: u8) {
+        let (cx1, cy1) = (self.clip1.0 as i32, self.clip1.1 as i32);
+        let (cx2, cy2) = (self.clip2.0 as i32, self.clip2.1 as i32);
+        if y < cy1 || y > cy2 {
+            return;
+        }
+        let x1 = x1.max(cx1);
+        let x2 = x2.min(cx2);
+        if x1 > x2 {
+            return;
+        }
+        if x2 - x1 < MIN_FILL_SPAN {
+            // not worth setting up the hardware for a few pixels
+            let row = (y * self.width as i32) as usize;
+            for el in &mut self.buf[row + x1 as usize..=row + x2 as usize] { *el = color; }
+        } else {
+            let y = y as u16;
+            self.impls.fill_rect(self.buf.as_mut(), x1 as u16, y, x2 as u16 + 1, y + 1, color);
+        }
+    }
+
+    /// Draw a circle outline with center (cx, cy) and radius r.
+    pub fn circle(&mut self, cx: u16, cy: u16, r: u16, color: u8) {
+        self.ellipse(cx, cy, r, r, color);
+    }
+
+    /// Draw a filled circle with center (cx, cy) and radius r.
+    pub fn fill_circle(&mut self, cx: u16, cy: u16, r: u16, color: u8) {
+        self.fill_ellipse(cx, cy, r, r, color);
+    }
+
+    /// Draw an ellipse outline with center (cx, cy) and radii rx and ry.
+    pub fn ellipse(&mut self, cx: u16, cy: u16, rx: u16, ry: u16, color: u8) {
+        let (cx, cy) = (cx as i32, cy as i32);
+        ellipse_quadrant(rx, ry, |x, y| {
+            self.set_pixel_signed(cx + x, cy + y, color);
+            self.set_pixel_signed(cx - x, cy + y, color);
+            self.set_pixel_signed(cx + x, cy - y, color);
+            self.set_pixel_signed(cx - x, cy - y, color);
+        });
+    }
+
+    /// Draw a filled ellipse with center (cx, cy) and radii rx and ry.
+    pub fn fill_ellipse(&mut self, cx: u16, cy: u16, rx: u16, ry: u16, color: u8) {
+        let (cx, cy) = (cx as i32, cy as i32);
+        ellipse_rows(rx, ry, |x, y| {
+            self.hline(cx - x, cx + x, cy + y, color);
+            if y != 0 {
+                self.hline(cx - x, cx + x, cy - y, color);
+            }
+        });
+    }
+
+    /// Draw a circular arc with center (cx, cy) and radius r.
+    ///
+    /// The arc goes counter-clockwise from angle `start` to `end`, which are
+    /// given in degrees, with 0 pointing to the right.
+    pub fn arc(&mut self, cx: u16, cy: u16, r: u16, start: u16, end: u16, color: u8) {
+        let (cx, cy) = (cx as i32, cy as i32);
+        let sector = Sector::new(start, end);
+        ellipse_quadrant(r, r, |x, y| {
+            for (dx, dy) in [(x, y), (-x, y), (x, -y), (-x, -y)] {
+                if sector.contains(dx, dy) {
+                    self.set_pixel_signed(cx + dx, cy + dy, color);
+                }
+            }
+        });
+    }
+
+    /// Draw a filled circle sector ("pie slice") with center (cx, cy) and
+    /// radius r, between angles `start` and `end` as for `arc`.
+    pub fn fill_arc(&mut self, cx: u16, cy: u16, r: u16, start: u16, end: u16, color: u8) {
+        let (cx, cy) = (cx as i32, cy as i32);
+        let sector = Sector::new(start, end);
+        ellipse_rows(r, r, |x, y| {
+            for dy in [y, -y].into_iter().take(if y == 0 { 1 } else { 2 }) {
+                // Draw consecutive runs of pixels inside the sector as spans.
+                let mut run_start = None;
+                for dx in -x..=x + 1 {
+                    let inside = dx <= x && sector.contains(dx, dy);
+                    match (inside, run_start) {
+                        (true, None) => run_start = Some(dx),
+                        (false, Some(rs)) => {
+                            self.hline(cx + rs, cx + dx - 1, cy + dy, color);
+                            run_start = None;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        });
+    }
+
+    /// Draw a filled polygon with the given vertices.  The polygon is closed
+    /// automatically, and filled using the even-odd rule.
+    pub fn fill_polygon<I>(&mut self, points: I, color: u8)
+    where I: Iterator<Item=(u16, u16)> + Clone
+    {
+        let Some(first) = points.clone().next() else { return };
+        let (ymin, ymax) = points.clone().fold((first.1, first.1), |(lo, hi), (_, y)| {
+            (lo.min(y), hi.max(y))
+        });
+        let edges = || points.clone().zip(points.clone().skip(1).chain(Some(first)));
+        let mut xs = [0i32; MAX_POLYGON_EDGES];
+        for y in ymin.max(self.clip1.1)..=ymax.min(self.clip2.1) {
+            let y = y as i32;
+            // find all intersections of the scanline with the polygon edges,
+            // excluding the lower endpoints so that vertices aren't counted twice
+            let mut n = 0;
+            for ((x1, y1), (x2, y2)) in edges() {
+                let (x1, y1, x2, y2) = if y1 < y2 { (x1, y1, x2, y2) } else { (x2, y2, x1, y1) };
+                let (x1, y1, x2, y2) = (x1 as i32, y1 as i32, x2 as i32, y2 as i32);
+                if y1 <= y && y < y2 {
+                    let x = x1 + ((y - y1) * (x2 - x1) * 2 + (y2 - y1)).div_euclid((y2 - y1) * 2);
+                    // insertion sort, intersections are few
+                    let mut i = n;
+                    while i > 0 && xs[i - 1] > x {
+                        xs[i] = xs[i - 1];
+                        i -= 1;
+                    }
+                    xs[i] = x;
+                    n += 1;
+                    if n == xs.len() {
+                        break;
+                    }
+                }
+            }
+            for pair in xs[..n].chunks_exact(2) {
+                self.hline(pair[0], pair[1], y, color);
+            }
+        }
+        // the outline makes sure edge pixels are drawn like for lines
+        for ((x1, y1), (x2, y2)) in edges() {
+            self.line(x1, y1, x2, y2, color);
+        }
+    }
+
     /// Copy a rectangle with (inclusive) coordinates (x1, y1) to (x2, y2), to
     /// destination top-left corner (dx, dy).
     pub fn copy_rect(&mut self, mut x1: u16, mut y1: u16, x2: u16, y2: u16, mut dx: u16, mut dy: u16) {
@@ -193,6 +551,25 @@ impl<'buf, Fb: FbImpl> FrameBuffer<'buf, Fb> {
         self.impls.copy_rect(self.buf.as_mut(), x1, y1, dx, dy, nx, ny);
     }
 
+    /// Replace the colors `from[i]` by `to[i]` in a rectangle with (inclusive)
+    /// coordinates (x1, y1) to (x2, y2); other colors are left alone.
+    ///
+    /// Mapping back from `to` to `from` restores the original contents, unless
+    /// `to` contains a color twice, or the rectangle contained colors from `to`
+    /// that are not in `from`.
+    pub fn map_colors(&mut self, x1: u16, y1: u16, x2: u16, y2: u16, from: &Palette, to: &Palette) {
+        let x2 = x2.min(self.width - 1);
+        let y2 = y2.min(self.height - 1);
+        for y in y1..=y2 {
+            let row = &mut self.buf[(y * self.width) as usize..][..self.width as usize];
+            for px in row.iter_mut().take(x2 as usize + 1).skip(x1 as usize) {
+                if let Some(i) = from.iter().position(|&c| c == *px) {
+                    *px = to[i];
+                }
+            }
+        }
+    }
+
     /// Scroll framebuffer up by *line_height* pixels.
     pub fn scroll_up(&mut self, line_height: u16) {
         self.impls.copy_rect(self.buf.as_mut(), 0, line_height, 0, 0, self.width, self.height);
@@ -205,6 +582,152 @@ impl<'buf, Fb: FbImpl> FrameBuffer<'buf, Fb> {
 
     /// Activate this framebuffer.
     pub fn activate(&mut self) {
-        self.impls.activate(self.buf.as_mut());
+        match self.back {
+            Some(ref mut front) if self.in_frame => self.impls.activate(front),
+            _ => self.impls.activate(self.buf),
+        }
+    }
+}
+
+/// Horizontal spans shorter than this are drawn pixel by pixel.
+const MIN_FILL_SPAN: i32 = 8;
+
+/// Maximum number of polygon edges that can cross a single scanline.
+const MAX_POLYGON_EDGES: usize = 128;
+
+/// Integer square root.
+fn isqrt(n: i32) -> i32 {
+    if n <= 0 {
+        return 0;
+    }
+    let mut x = n;
+    let mut y = (x + 1) / 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+/// Horizontal inset of a corner with radius r in row i, counted from the
+/// top or bottom edge.
+fn corner_inset(r: i32, i: i32) -> i32 {
+    if i >= r {
+        return 0;
+    }
+    let dy = r - i;
+    // adding r rounds the circle in the same way as the midpoint algorithm
+    r - isqrt(r*r - dy*dy + r).min(r)
+}
+
+/// Call `plot` for all points (x, y) of the first quadrant of an ellipse with
+/// radii rx and ry, centered at the origin.  Uses the midpoint algorithm.
+fn ellipse_quadrant(rx: u16, ry: u16, mut plot: impl FnMut(i32, i32)) {
+    if rx == 0 || ry == 0 {
+        // degenerate ellipse: just a line
+        for x in 0..=rx as i32 {
+            plot(x, 0);
+        }
+        for y in 0..=ry as i32 {
+            plot(0, y);
+        }
+        return;
+    }
+    let (a2, b2) = ((rx as i64) * (rx as i64), (ry as i64) * (ry as i64));
+    let (mut x, mut y) = (0, ry as i64);
+    let (mut dx, mut dy) = (0, 2 * a2 * y);
+    // region 1: slope of the curve is below 1
+    let mut d = b2 - a2 * y + a2 / 4;
+    while dx < dy {
+        plot(x as i32, y as i32);
+        x += 1;
+        dx += 2 * b2;
+        if d < 0 {
+            d += dx + b2;
+        } else {
+            y -= 1;
+            dy -= 2 * a2;
+            d += dx - dy + b2;
+        }
+    }
+    // region 2: slope of the curve is above 1
+    d = b2 * (2 * x + 1) * (2 * x + 1) / 4 + a2 * (y - 1) * (y - 1) - a2 * b2;
+    while y >= 0 {
+        plot(x as i32, y as i32);
+        y -= 1;
+        dy -= 2 * a2;
+        if d > 0 {
+            d += a2 - dy;
+        } else {
+            x += 1;
+            dx += 2 * b2;
+            d += dx - dy + a2;
+        }
+    }
+}
+
+/// Call `row` once for every row y of the first quadrant of an ellipse, with
+/// the maximum x coordinate in that row.
+fn ellipse_rows(rx: u16, ry: u16, mut row: impl FnMut(i32, i32)) {
+    let mut last: Option<(i32, i32)> = None;
+    ellipse_quadrant(rx, ry, |x, y| {
+        match last {
+            Some((_, ly)) if ly == y => {}
+            Some((lx, ly)) => row(lx, ly),
+            None => {}
+        }
+        last = Some((x, y));
+    });
+    if let Some((lx, ly)) = last {
+        row(lx, ly);
+    }
+}
+
+/// Fixed point scale for `sin_deg`.
+const SIN_ONE: i32 = 1 << 12;
+
+/// Integer approximation of the sine of an angle in degrees, scaled by
+/// `SIN_ONE`.  Uses Bhaskara's formula, which is precise enough for drawing.
+fn sin_deg(deg: u16) -> i32 {
+    let deg = (deg % 360) as i32;
+    let (x, sign) = if deg < 180 { (deg, 1) } else { (deg - 180, -1) };
+    let p = x * (180 - x);
+    sign * 4 * p * SIN_ONE / (40500 - p)
+}
+
+/// A circle sector between two angles, used for arcs.
+struct Sector {
+    // direction vectors of start and end angle (y axis pointing down)
+    start: (i32, i32),
+    end: (i32, i32),
+    // true if the sector is larger than a half circle
+    reflex: bool,
+    // true if the sector is the full circle
+    full: bool,
+}
+
+impl Sector {
+    fn new(start: u16, end: u16) -> Self {
+        let (start, end) = (start % 360, end % 360);
+        let sweep = (end + 360 - start) % 360;
+        let dir = |a| (sin_deg(a + 90), -sin_deg(a));
+        Self { start: dir(start), end: dir(end), reflex: sweep > 180,
+               full: start == end }
+    }
+
+    /// Check if the vector (x, y) from the center lies within the sector.
+    fn contains(&self, x: i32, y: i32) -> bool {
+        if self.full {
+            return true;
+        }
+        // with the y axis pointing down, a positive cross product means
+        // that (x, y) is clockwise from the given vector
+        let after_start = self.start.0 * y - self.start.1 * x <= 0;
+        let before_end = self.end.0 * y - self.end.1 * x >= 0;
+        if self.reflex {
+            after_start || before_end
+        } else {
+            after_start && before_end
+        }
     }
 }
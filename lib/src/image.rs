@@ -1,4 +1,4 @@
-//! Stock image definitions.
+//! Stock image definitions and storage for uploaded images.
 
 use crate::interface::Palette;
 
@@ -14,3 +14,90 @@ pub const IMAGES: &[ImageDef] = &[
     #[cfg(feature = "customer-fau")]
     (include_bytes!("logo_fau.dat"), (240, 88), [24, 99, 146, 15]),
 ];
+
+/// Number of image slots in RAM that can be uploaded by the host.
+pub const RAM_IMAGES: usize = 16;
+
+/// Image number of the first RAM slot, as used by the IMAGE command.
+pub const RAM_IMAGE_BASE: u8 = 0x80;
+
+#[derive(Default, Clone, Copy)]
+struct Slot {
+    offset: usize,
+    len: usize,
+    size: (u16, u16),
+    pal: Palette,
+}
+
+/// Storage for 2bpp images uploaded by the host.
+///
+/// All images share one buffer, which is compacted when an image is
+/// redefined with a different size.
+pub struct ImageStore<'buf> {
+    buf: &'buf mut [u8],
+    slots: [Slot; RAM_IMAGES],
+}
+
+impl<'buf> ImageStore<'buf> {
+    pub fn new(buf: &'buf mut [u8]) -> Self {
+        Self { buf, slots: Default::default() }
+    }
+
+    /// Define the size and default palette of an image slot, and clear its
+    /// data.  Returns false if there is not enough space left.
+    pub fn define(&mut self, slot: usize, size: (u16, u16), pal: Palette) -> bool {
+        let len = (size.0 as usize * size.1 as usize).div_ceil(4);
+        if self.slots[slot].len != len {
+            self.slots[slot].len = 0;
+            let end = self.compact();
+            if end + len > self.buf.len() {
+                return false;
+            }
+            self.slots[slot].offset = end;
+            self.slots[slot].len = len;
+        }
+        let Slot { offset, .. } = self.slots[slot];
+        self.buf[offset..offset + len].fill(0);
+        self.slots[slot].size = size;
+        self.slots[slot].pal = pal;
+        true
+    }
+
+    /// Write a chunk of image data at the given byte offset.  Returns false if
+    /// the data does not fit into the image.
+    pub fn write(&mut self, slot: usize, offset: usize, data: &[u8]) -> bool {
+        let Slot { offset: start, len, .. } = self.slots[slot];
+        if offset + data.len() > len {
+            return false;
+        }
+        self.buf[start + offset..start + offset + data.len()].copy_from_slice(data);
+        true
+    }
+
+    /// Get data, size and default palette of an image, if it is defined.
+    pub fn get(&self, slot: usize) -> Option<(&[u8], (u16, u16), Palette)> {
+        let Slot { offset, len, size, pal } = *self.slots.get(slot)?;
+        if len == 0 {
+            return None;
+        }
+        Some((&self.buf[offset..offset + len], size, pal))
+    }
+
+    /// Move all images to the start of the buffer, and return the end of
+    /// the used space.
+    fn compact(&mut self) -> usize {
+        // sort the slot indices by current offset
+        let mut order: [usize; RAM_IMAGES] = core::array::from_fn(|i| i);
+        order.sort_unstable_by_key(|&i| self.slots[i].offset);
+        let mut end = 0;
+        for i in order {
+            let Slot { offset, len, .. } = self.slots[i];
+            if len > 0 {
+                self.buf.copy_within(offset..offset + len, end);
+                self.slots[i].offset = end;
+                end += len;
+            }
+        }
+        end
+    }
+}
@@ -1,8 +1,14 @@
 //! The command interface to a client.
 
-use crate::image::IMAGES;
-use crate::console::{Console, WriteToHost};
-use crate::framebuf::{FONTS, FrameBuffer, FbImpl};
+use crate::image::{IMAGES, RAM_IMAGES, RAM_IMAGE_BASE, ImageStore};
+use crate::console::{Console, WriteToHost, COLS, ROWS};
+use crate::framebuf::{FONTS, Align, FrameBuffer, FbImpl};
+use crate::overlay::Overlay;
+use crate::backlight::{Backlight, BACKLIGHT_SETTINGS_LEN};
+use crate::palette::{PaletteAnimator, Rgb};
+use crate::screensaver::{ScreenSaver, SaverMode};
+use crate::widget::{Bar, Label, Button, Chart, Samples, MAX_BARS, MAX_LABELS, MAX_BUTTONS,
+                    MAX_CHARTS, MAX_CHANNELS};
 
 /// A 2-bit color palette. Order is `[bg, .., .., fg]`.
 pub type Palette = [u8; 4];
@@ -11,11 +17,20 @@ const ESCAPE:            u8 = 0x1b;
 
 const CMD_MODE_GRAPHICS: u8 = 0x20;
 const CMD_MODE_CONSOLE:  u8 = 0x21;
+const CMD_BEGIN_FRAME:   u8 = 0x22;
+const CMD_END_FRAME:     u8 = 0x23;
+const CMD_OVERLAY:       u8 = 0x24;
 
 const CMD_SET_POS:       u8 = 0x30;
 const CMD_SET_FONT:      u8 = 0x31;
 const CMD_SET_COLOR:     u8 = 0x32;
 const CMD_SET_CLIP:      u8 = 0x33;
+const CMD_SET_ALIGN:     u8 = 0x34;
+const CMD_SET_CLUT:      u8 = 0x35;
+const CMD_RESET_CLUT:    u8 = 0x36;
+const CMD_ANIMATE_CLUT:  u8 = 0x37;
+const CMD_SET_BACKLIGHT: u8 = 0x38;
+const CMD_SET_SAVER:     u8 = 0x39;
 
 const CMD_CLEAR:         u8 = 0x40;
 const CMD_LINES:         u8 = 0x41;
@@ -25,10 +40,41 @@ const CMD_TEXT:          u8 = 0x44;
 const CMD_COPYRECT:      u8 = 0x45;
 const CMD_PLOT:          u8 = 0x46;
 const CMD_PIXELS:        u8 = 0x47;
+const CMD_CIRCLE:        u8 = 0x48;
+const CMD_ELLIPSE:       u8 = 0x49;
+const CMD_ARC:           u8 = 0x4a;
+const CMD_POLYGON:       u8 = 0x4b;
+const CMD_RECT_OUTLINE:  u8 = 0x4c;
+const CMD_ROUND_RECT:    u8 = 0x4d;
+const CMD_TEXT_SIZE:     u8 = 0x4e;
+const CMD_TEXT_BOX:      u8 = 0x4f;
 
 const CMD_TOUCH:         u8 = 0x50;  // only for replies
 const CMD_TOUCH_MODE:    u8 = 0x51;
 const CMD_TOUCH_CALIB:   u8 = 0x52;
+const CMD_BUTTON:        u8 = 0x53;  // only for replies
+const CMD_BUTTON_DEFINE: u8 = 0x54;
+const CMD_TOUCH_CALIBRATE: u8 = 0x55;
+const CMD_TOUCH_EVENT:   u8 = 0x56;  // only for replies
+
+const CMD_IMAGE_DEFINE:  u8 = 0x60;
+const CMD_IMAGE_DATA:    u8 = 0x61;
+const CMD_PIXELS_1BPP:   u8 = 0x62;
+const CMD_PIXELS_2BPP:   u8 = 0x63;
+const CMD_PIXELS_4BPP:   u8 = 0x64;
+const CMD_PIXELS_RLE:    u8 = 0x65;
+const CMD_SCREENSHOT:    u8 = 0x66;
+
+const CMD_ACK_MODE:      u8 = 0x70;
+const CMD_ACK:           u8 = 0x71;  // only for replies
+const CMD_CRC_MODE:      u8 = 0x72;
+
+const CMD_BAR_DEFINE:    u8 = 0x80;
+const CMD_BAR_VALUE:     u8 = 0x81;
+const CMD_LABEL_DEFINE:  u8 = 0x82;
+const CMD_LABEL_TEXT:    u8 = 0x83;
+const CMD_CHART_DEFINE:  u8 = 0x84;
+const CMD_CHART_PUSH:    u8 = 0x85;
 
 const CMD_SAVE_ATTRS:    u8 = 0xa0;
 const CMD_SAVE_ATTRS_MAX:u8 = 0xbf;
@@ -42,9 +88,75 @@ const CMD_SET_STARTUP:   u8 = 0xf2;
 const CMD_IDENT:         u8 = 0xf3;
 const CMD_RESET_APU:     u8 = 0xf4;
 const CMD_APU_REINSTALL: u8 = 0xf5;
+const CMD_CAPABILITIES:  u8 = 0xf6;
+
+/// Maximum length of a command (command byte and data) that is not streamed.
+pub const MAX_CMD_LEN: usize = 255;
+
+/// Bits for optional commands in the reply to CAPABILITIES.
+pub const CAP_FRAMES:      u32 = 1 << 0;  // BEGIN_FRAME and END_FRAME draw off-screen
+pub const CAP_OVERLAY:     u32 = 1 << 1;
+pub const CAP_ACK:         u32 = 1 << 2;
+pub const CAP_CRC:         u32 = 1 << 3;
+pub const CAP_STREAMING:   u32 = 1 << 4;  // 16-bit length for bulk data
+pub const CAP_RAM_IMAGES:  u32 = 1 << 5;
+pub const CAP_SCREENSHOT:  u32 = 1 << 6;
+pub const CAP_WIDGETS:     u32 = 1 << 7;  // bars, labels and charts
+pub const CAP_BUTTONS:     u32 = 1 << 8;
+pub const CAP_TOUCH_EVENTS:u32 = 1 << 9;
+pub const CAP_CLUT:        u32 = 1 << 10;
+pub const CAP_BACKLIGHT:   u32 = 1 << 11;
+pub const CAP_SAVER:       u32 = 1 << 12;
+pub const CAP_SAVER_LOGO:  u32 = 1 << 13;
+
+const CRC_INIT:       u16 = 0xffff;
 
 const BOOT_STRING:    &[u8] = b"\x1b[0mSeaBIOS ";
 
+/// EEPROM addresses of the length and data of the stored touch calibration.
+pub const EEPROM_TOUCH_CALIB: (usize, usize) = (4, 384);
+/// EEPROM addresses of the length and data of the stored backlight settings.
+pub const EEPROM_BACKLIGHT: (usize, usize) = (6, 448);
+
+/// Time for which a touched button stays highlighted, in milliseconds.
+const BUTTON_HIGHLIGHT_MS: u16 = 300;
+
+/// Kinds of events sent in touch event mode.
+const TOUCH_PRESS:      u8 = 0;
+const TOUCH_RELEASE:    u8 = 1;
+const TOUCH_LONG_PRESS: u8 = 2;
+const TOUCH_SWIPE:      u8 = 3;
+
+/// Minimum duration of a long press, in milliseconds.
+const LONG_PRESS_MS: u32 = 1000;
+/// Minimum horizontal distance of a swipe, in pixels.
+const SWIPE_MIN_DX: u16 = 80;
+
+/// Maximum number of pixels sent in one reply to the SCREENSHOT command.
+const SCREENSHOT_CHUNK: usize = 240;
+
+/// Status codes sent back for each command in ack mode.
+#[derive(Clone, Copy)]
+enum Status {
+    Ok = 0,
+    UnknownCommand = 1,
+    BadLength = 2,
+    OutOfRange = 3,
+    /// A streamed command was corrupted; its data has already been used.
+    BadCrc = 4,
+}
+
+/// What happens when the screen is touched outside of buttons.
+#[derive(Clone, Copy, PartialEq)]
+enum TouchMode {
+    /// Switch between graphics and console display.
+    Toggle,
+    /// Send the position of each press to the host.
+    Forward,
+    /// Send press, release, long-press and swipe events to the host.
+    Events,
+}
+
 /// All stateful settings for graphics drawing.
 #[derive(Default, Clone, Copy)]
 pub struct GraphicsSetting {
@@ -54,12 +166,16 @@ pub struct GraphicsSetting {
     pub clip2: (u16, u16),
     pub font:  u8,
     pub pal:   Palette,
+    pub align: Align,
 }
 
 pub trait TouchHandler {
     type Event;
+    /// Wait for the next touch event and return its position.
     fn wait(&self) -> (u16, u16);
+    /// Convert a raw touch event to a position on the display.
     fn convert(&self, ev: Self::Event) -> (u16, u16);
+    /// Set the calibration as (x divisor, x offset, y divisor, y offset).
     fn set_calib(&mut self, data: (u16, u16, u16, u16));
 }
 
@@ -69,18 +185,46 @@ pub struct DisplayState<'buf, Tx, Th, Impl> {
     // second framebuffer for console mode
     con: Console<'buf, Tx, Impl>,
     touch: Th,
+    // images uploaded by the host
+    images: ImageStore<'buf>,
+    // notification window shown over both framebuffers
+    overlay: Overlay<'buf, Impl>,
+    // animated color table entries
+    palette: PaletteAnimator,
+    backlight: Backlight,
+    saver: ScreenSaver,
     // current graphics settings
     cur: GraphicsSetting,
     // graphics settings for SET/SEL_ATTRS
     saved: [GraphicsSetting; 32],
+    // bar graph widgets
+    bars: [Option<Bar>; MAX_BARS],
+    // text label widgets
+    labels: [Option<Label>; MAX_LABELS],
+    // strip chart widgets
+    charts: [Option<Chart>; MAX_CHARTS],
+    // touch buttons
+    buttons: [Option<Button>; MAX_BUTTONS],
+    // highlighted button, and time left until the highlight is removed
+    pressed: Option<(usize, u16)>,
     // escape parsing
     escape: Escape,
-    escape_seq: [u8; 256],
+    // room for the maximum length, command and data, and the CRC
+    escape_seq: [u8; 1 + MAX_CMD_LEN + 2],
     // if true, graphics display is currently active
     gfx_mode: bool,
-    // if true, we forward touch events to the host
-    // else, touch switches between graphics and console
-    fwd_touch: bool,
+    touch_mode: TouchMode,
+    // position and duration of the current touch, in event mode
+    touch_start: Option<((u16, u16), u32)>,
+    // if set, every command is acknowledged with this sequence number and status
+    ack_seq: Option<u8>,
+    // if true, every graphics command must be followed by a CRC
+    crc_mode: bool,
+    // graphics command that is too long for the escape buffer
+    stream: Stream,
+    stream_status: Status,
+    // running CRC over the streamed command, and the CRC sent by the host
+    stream_crc: (u16, u16),
 }
 
 /// Actions that the interface delegates to higher-level layer.
@@ -90,7 +234,20 @@ pub enum Action<'a> {
     Bootloader,
     ResetApu,
     ApuReinstall,
-    WriteEeprom(usize, usize, &'a [u8])
+    WriteEeprom(usize, usize, &'a [u8]),
+    CalibrateTouch,
+    /// Set CLUT entries from this index to the given RGB triplets.
+    SetLut(u8, &'a [u8]),
+    /// Reset the CLUT to the default colors.
+    ResetLut,
+    /// Set the CLUT entries with these indices to new colors.
+    UpdateLut(&'a [(u8, Rgb)]),
+    /// Set the backlight brightness (0-255).
+    SetBacklight(u8),
+    /// Switch the display off (true) or on again (false).
+    BlankDisplay(bool),
+    ShowOverlay(&'a [u8]),
+    HideOverlay,
 }
 
 /// State machine for escape-sequence parsing.
@@ -99,9 +256,112 @@ enum Escape {
     SawOne,
     Csi(usize),
     Graphics(usize, usize),
+    Stream(usize, usize),
     MayBeBooting(usize),
 }
 
+/// Graphics command whose data is processed while it arrives.
+enum Stream {
+    Skip,
+    Pixels(PixelStream),
+    ImageData(usize, usize),
+}
+
+/// Position of the next pixel for the PIXELS family of commands.
+struct PixelCursor {
+    x0: u16,
+    x: u16,
+    y: u16,
+    x_end: u16,
+    y_end: u16,
+    sx: u16,
+    sy: u16,
+}
+
+impl PixelCursor {
+    /// Create a cursor from the command header, which contains the top-left
+    /// position, the number of pixels in x and y direction, and the scale in x
+    /// and y direction.
+    fn new(header: &[u8]) -> Self {
+        let (x0, y0) = pos_from_bytes(&header[0..]);
+        let (nx, ny) = pos_from_bytes(&header[2..]);
+        let (sx, sy) = pos_from_bytes(&header[4..]);
+        let (sx, sy) = (sx.max(1), sy.max(1));
+        let x_end = x0.saturating_add(nx.saturating_mul(sx));
+        let y_end = y0.saturating_add(ny.saturating_mul(sy));
+        // nothing to draw if there are no pixels in a row
+        let y = if nx == 0 { y_end } else { y0 };
+        Self { x0, x: x0, y, x_end, y_end, sx, sy }
+    }
+
+    /// Draw the next pixel.  Returns false if all pixels have been drawn.
+    fn put<Fb: FbImpl>(&mut self, gfx: &mut FrameBuffer<'_, Fb>, color: u8) -> bool {
+        if self.y >= self.y_end {
+            return false;
+        }
+        if self.sx > 1 || self.sy > 1 {
+            // the rectangle would be clamped to the edge if it started outside
+            if self.x < gfx.width() && self.y < gfx.height() {
+                gfx.rect(self.x, self.y, (self.x_end - 1).min(self.x.saturating_add(self.sx - 1)),
+                         (self.y_end - 1).min(self.y.saturating_add(self.sy - 1)), color);
+            }
+        } else {
+            gfx.set_pixel(self.x, self.y, color);
+        }
+        self.x = self.x.saturating_add(self.sx);
+        if self.x >= self.x_end {
+            self.x = self.x0;
+            self.y = self.y.saturating_add(self.sy);
+        }
+        true
+    }
+}
+
+/// Decoder for the data of the PIXELS family of commands, which can be fed
+/// byte by byte.
+struct PixelStream {
+    cmd: u8,
+    cursor: PixelCursor,
+    // pending run length for RLE data
+    run: Option<u8>,
+}
+
+impl PixelStream {
+    fn new(cmd: u8, header: &[u8]) -> Self {
+        Self { cmd, cursor: PixelCursor::new(header), run: None }
+    }
+
+    fn feed<Fb: FbImpl>(&mut self, gfx: &mut FrameBuffer<'_, Fb>, pal: &Palette, byte: u8) {
+        match self.cmd {
+            CMD_PIXELS_1BPP => for v in unpack_bits(byte, 1) {
+                self.cursor.put(gfx, if v == 0 { pal[0] } else { pal[3] });
+            }
+            CMD_PIXELS_2BPP => for v in unpack_bits(byte, 2) {
+                self.cursor.put(gfx, pal[v as usize]);
+            }
+            // 4-bit values directly select one of the 16 basic colors
+            CMD_PIXELS_4BPP => for v in unpack_bits(byte, 4) {
+                self.cursor.put(gfx, v);
+            }
+            // pairs of (run length, color)
+            CMD_PIXELS_RLE => match self.run.take() {
+                Some(n) => for _ in 0..n {
+                    self.cursor.put(gfx, byte);
+                }
+                None => self.run = Some(byte),
+            }
+            _ => {
+                self.cursor.put(gfx, byte);
+            }
+        }
+    }
+
+    /// If there was less data than pixels, fill the rest with the foreground color.
+    fn finish<Fb: FbImpl>(&mut self, gfx: &mut FrameBuffer<'_, Fb>, pal: &Palette) {
+        while self.cursor.put(gfx, pal[3]) {}
+    }
+}
+
 /// Extract an (x, y) position from two bytes.  Since 0<x<480 but 0<y<128
 /// it still fits but the extra bit needs to be shuffled.
 fn pos_from_bytes(pos: &[u8]) -> (u16, u16) {
@@ -109,21 +369,74 @@ fn pos_from_bytes(pos: &[u8]) -> (u16, u16) {
      (pos[0] >> 1) as u16)
 }
 
+/// Extract a 16-bit value (e.g. an angle in degrees) from two bytes (big-endian).
+fn u16_from_bytes(val: &[u8]) -> u16 {
+    ((val[0] as u16) << 8) | (val[1] as u16)
+}
+
+/// Extract a signed 16-bit value from two bytes (big-endian).
+fn i16_from_bytes(val: &[u8]) -> i16 {
+    u16_from_bytes(val) as i16
+}
+
+/// Continue calculating the CRC-16/CCITT (polynomial 0x1021) of the given
+/// bytes.  The calculation starts with `CRC_INIT`.
+fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Get the RAM image slot from an image number, if it refers to one.
+fn ram_image_slot(num: u8) -> Option<usize> {
+    let slot = num.checked_sub(RAM_IMAGE_BASE)? as usize;
+    (slot < RAM_IMAGES).then_some(slot)
+}
+
+/// Unpack values of the given bit size from a byte, starting with the lowest
+/// bits.
+fn unpack_bits(byte: u8, bits: u8) -> impl Iterator<Item=u8> {
+    let mask = (1 << bits) - 1;
+    (0..8 / bits).map(move |i| (byte >> (i * bits)) & mask)
+}
+
+/// Length of the header of a command that can be streamed, which must be
+/// received completely before the data is processed.  Zero if the command
+/// can't be streamed.
+fn stream_header_len(cmd: u8) -> usize {
+    match cmd {
+        CMD_PIXELS | CMD_PIXELS_1BPP | CMD_PIXELS_2BPP | CMD_PIXELS_4BPP | CMD_PIXELS_RLE => 6,
+        CMD_IMAGE_DATA => 3,
+        _ => 0,
+    }
+}
+
 fn pos_to_bytes(x: u16, y: u16) -> (u8, u8) {
     ((y << 1) as u8 | (x >> 8) as u8, x as u8)
 }
 
 impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx, Th, Fb> {
-    pub fn new(mut gfx: FrameBuffer<'buf, Fb>, con: Console<'buf, Tx, Fb>, touch: Th) -> Self {
+    pub fn new(mut gfx: FrameBuffer<'buf, Fb>, con: Console<'buf, Tx, Fb>, touch: Th,
+               images: ImageStore<'buf>, overlay: Overlay<'buf, Fb>) -> Self {
         gfx.clear(255);
         let default_setting = GraphicsSetting {
             clip2: (gfx.width() - 1, gfx.height() - 1), .. Default::default()
         };
         Self {
             gfx, con, cur: default_setting, saved: Default::default(),
-            escape: Escape::None, escape_seq: [0; 256],
-            gfx_mode: false, fwd_touch: false,
-            touch,
+            bars: [None; MAX_BARS], labels: [None; MAX_LABELS], charts: [None; MAX_CHARTS],
+            buttons: [None; MAX_BUTTONS], pressed: None,
+            escape: Escape::None, escape_seq: [0; 1 + MAX_CMD_LEN + 2],
+            gfx_mode: false, touch_mode: TouchMode::Toggle, touch_start: None, ack_seq: None,
+            crc_mode: false, stream: Stream::Skip, stream_status: Status::Ok,
+            stream_crc: (0, 0),
+            palette: PaletteAnimator::default(), backlight: Backlight::default(),
+            saver: ScreenSaver::default(),
+            touch, images, overlay,
         }
     }
 
@@ -139,8 +452,20 @@ impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx,
         &mut self.gfx
     }
 
-    pub fn split(&mut self) -> (&mut FrameBuffer<'buf, Fb>, &mut Console<'buf, Tx, Fb>, &Th) {
-        (&mut self.gfx, &mut self.con, &self.touch)
+    /// Apply backlight settings, e.g. stored ones at startup.  The new
+    /// brightness is returned by the next `tick`.
+    pub fn configure_backlight(&mut self, settings: &[u8]) {
+        self.backlight.configure(settings);
+    }
+
+    /// Apply the host's clip rectangle again, after drawing on the graphics
+    /// with a different one.
+    pub fn restore_clip(&mut self) {
+        self.gfx.set_clip(self.cur.clip1, self.cur.clip2);
+    }
+
+    pub fn split(&mut self) -> (&mut FrameBuffer<'buf, Fb>, &mut Console<'buf, Tx, Fb>, &mut Th) {
+        (&mut self.gfx, &mut self.con, &mut self.touch)
     }
 
     /// Main entry point to feed a new byte from remote.
@@ -149,6 +474,8 @@ impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx,
     /// display, terminal sequences operate on the console), and normal
     /// characters are drawn to the console.
     pub fn process_byte(&mut self, ch: u8) -> Action<'_> {
+        self.backlight.activity();
+        self.wake_saver();
         match self.escape {
             Escape::None => {
                 if ch == ESCAPE {
@@ -186,21 +513,50 @@ impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx,
                 }
             }
             Escape::Graphics(ref mut pos, ref mut len) => {
-                if *len == 0 {
-                    if ch == 0 {
+                self.escape_seq[*pos] = ch;
+                *pos += 1;
+                // a length byte of zero introduces a 16-bit length
+                let start = if self.escape_seq[0] == 0 { 3 } else { 1 };
+                if *len == 0 && *pos == start {
+                    let cmd_len = if start == 1 { ch as usize } else {
+                        u16_from_bytes(&self.escape_seq[1..]) as usize
+                    };
+                    if cmd_len == 0 {
                         // length of zero is not allowed
                         self.escape = Escape::None;
                         return Action::None;
-                    } else {
-                        *len = ch as usize + 1;
                     }
-                }
-                self.escape_seq[*pos] = ch;
-                *pos += 1;
-                if *pos == *len {
-                    let escape_len = *pos;
+                    *len = start + cmd_len + if self.crc_mode { 2 } else { 0 };
+                } else if *pos == *len {
+                    let mut escape_len = *pos;
                     self.escape = Escape::None;
-                    return self.process_command(escape_len);
+                    if self.crc_mode {
+                        escape_len -= 2;
+                        let crc = u16_from_bytes(&self.escape_seq[escape_len..]);
+                        if crc16(CRC_INIT, &self.escape_seq[..escape_len]) != crc {
+                            // corrupted command, drop it
+                            return Action::None;
+                        }
+                    }
+                    return self.process_command(start - 1, escape_len);
+                } else if *len > self.escape_seq.len() &&
+                    *pos == start + 1 + stream_header_len(self.escape_seq[start])
+                {
+                    // too long for the buffer, process the data as it arrives
+                    let (pos, len) = (*pos, *len);
+                    self.start_stream(start, pos, len);
+                }
+            }
+            Escape::Stream(pos, len) => {
+                self.escape = if pos + 1 == len { Escape::None } else { Escape::Stream(pos + 1, len) };
+                if self.crc_mode && pos + 2 >= len {
+                    self.stream_crc.1 = (self.stream_crc.1 << 8) | ch as u16;
+                } else {
+                    self.stream_crc.0 = crc16(self.stream_crc.0, &[ch]);
+                    self.stream_byte(ch);
+                }
+                if pos + 1 == len {
+                    self.finish_stream();
                 }
             }
             Escape::MayBeBooting(ref mut pos) => {
@@ -222,29 +578,282 @@ impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx,
         Action::None
     }
 
-    /// Main entry point to feed a new touch event.
+    /// Main entry point for the passage of time, should be called regularly
+    /// with the milliseconds elapsed since the last call.
     ///
-    /// Depending on the touch mode, the event is written to remote or
-    /// switches between graphics and console display.
+    /// If more than one action is due, the others are returned by later calls.
+    pub fn tick(&mut self, ms: u16) -> Action<'_> {
+        match self.pressed {
+            Some((i, left)) if left > ms => self.pressed = Some((i, left - ms)),
+            Some(_) => Self::release_button(&mut self.gfx, &self.buttons, &mut self.pressed),
+            None => (),
+        }
+        if let Some((pos, duration)) = &mut self.touch_start {
+            let was_short = *duration < LONG_PRESS_MS;
+            *duration = duration.saturating_add(ms as u32);
+            if was_short && *duration >= LONG_PRESS_MS && self.touch_mode == TouchMode::Events {
+                let held = (*duration).min(u16::MAX as u32) as u16;
+                Self::send_touch_event(&mut self.con, TOUCH_LONG_PRESS, *pos, held);
+            }
+        }
+        self.palette.tick(ms);
+        self.backlight.tick(ms);
+        self.saver.tick(&mut self.gfx, ms);
+        if self.overlay.tick(ms) {
+            return Action::HideOverlay;
+        }
+        if let Some(level) = self.backlight.take_change() {
+            return Action::SetBacklight(level);
+        }
+        if let Some(blank) = self.saver.take_blank_change() {
+            return Action::BlankDisplay(blank);
+        }
+        let updates = self.palette.take_updates();
+        if !updates.is_empty() {
+            return Action::UpdateLut(updates);
+        }
+        Action::None
+    }
+
+    /// Main entry point to feed a new touch press event.
+    ///
+    /// If the backlight is dimmed or the screen saver is active, the touch
+    /// only restores the display.  If a button
+    /// is touched on the graphics display, it is highlighted and its ID is
+    /// written to remote.  Otherwise, depending on the touch mode, the event
+    /// is written to remote or switches between graphics and console display.
     pub fn process_touch(&mut self, ev: Th::Event) -> (u16, u16) {
         let (x, y) = self.touch.convert(ev);
-        if self.fwd_touch {
-            let (b0, b1) = pos_to_bytes(x, y);
-            self.con.write_to_host(&[ESCAPE, ESCAPE, 0x03, CMD_TOUCH, b0, b1]);
+        if self.backlight.activity() | self.wake_saver() {
+            return (x, y);
+        }
+        let button = if self.gfx_mode {
+            self.buttons.iter().position(|b| b.is_some_and(|b| b.contains((x, y))))
         } else {
-            self.gfx_mode = !self.gfx_mode;
-            if self.gfx_mode {
-                self.gfx.activate();
-            } else {
-                self.con.activate();
+            None
+        };
+        if let Some(i) = button {
+            Self::release_button(&mut self.gfx, &self.buttons, &mut self.pressed);
+            if let Some(b) = &self.buttons[i] {
+                b.set_highlight(&mut self.gfx, true);
+            }
+            self.pressed = Some((i, BUTTON_HIGHLIGHT_MS));
+            self.con.write_to_host(&[ESCAPE, ESCAPE, 0x02, CMD_BUTTON, i as u8]);
+        } else {
+            match self.touch_mode {
+                TouchMode::Forward => {
+                    let (b0, b1) = pos_to_bytes(x, y);
+                    self.con.write_to_host(&[ESCAPE, ESCAPE, 0x03, CMD_TOUCH, b0, b1]);
+                }
+                TouchMode::Events => {
+                    self.touch_start = Some(((x, y), 0));
+                    Self::send_touch_event(&mut self.con, TOUCH_PRESS, (x, y), 0);
+                }
+                TouchMode::Toggle => {
+                    self.gfx_mode = !self.gfx_mode;
+                    if self.gfx_mode {
+                        self.gfx.activate();
+                    } else {
+                        self.con.activate();
+                    }
+                }
             }
         }
         (x, y)
     }
 
-    fn process_command(&mut self, len: usize) -> Action<'_> {
-        let cmd = &self.escape_seq[..len];
+    /// Main entry point to feed a new touch release event, with the last
+    /// position touched.
+    ///
+    /// A pressed button is released.  In event mode, the release is written
+    /// to remote, followed by a swipe event if the touch has moved far
+    /// enough horizontally.  (Long-press events are sent by `tick` while the
+    /// screen is still touched.)
+    pub fn process_release(&mut self, ev: Th::Event) {
+        let (x, y) = self.touch.convert(ev);
+        Self::release_button(&mut self.gfx, &self.buttons, &mut self.pressed);
+        let Some(((x0, y0), duration)) = self.touch_start.take() else { return };
+        if self.touch_mode != TouchMode::Events {
+            return;
+        }
+        let duration = duration.min(u16::MAX as u32) as u16;
+        Self::send_touch_event(&mut self.con, TOUCH_RELEASE, (x, y), duration);
+        let dx = x as i16 - x0 as i16;
+        if dx.unsigned_abs() >= SWIPE_MIN_DX && dx.unsigned_abs() > 2 * y.abs_diff(y0) {
+            Self::send_touch_event(&mut self.con, TOUCH_SWIPE, (x, y), dx as u16);
+        }
+    }
+
+    /// Register activity for the screen saver.  If it was active, restore the
+    /// previously shown framebuffer and return true.
+    fn wake_saver(&mut self) -> bool {
+        if !self.saver.activity(&mut self.gfx) {
+            return false;
+        }
+        self.gfx.set_clip(self.cur.clip1, self.cur.clip2);
+        if self.gfx_mode {
+            self.gfx.activate();
+        } else {
+            self.con.activate();
+        }
+        true
+    }
+
+    /// Send a touch event of the given kind, with position and a value that
+    /// depends on the kind: the duration in milliseconds for release and
+    /// long-press, the signed horizontal distance for swipes.
+    fn send_touch_event(con: &mut Console<'buf, Tx, Fb>, kind: u8, (x, y): (u16, u16), value: u16) {
+        let (b0, b1) = pos_to_bytes(x, y);
+        con.write_to_host(&[ESCAPE, ESCAPE, 0x06, CMD_TOUCH_EVENT, kind, b0, b1,
+                            (value >> 8) as u8, value as u8]);
+    }
+
+    /// Remove the highlight from the pressed button, if any.
+    fn release_button(gfx: &mut FrameBuffer<'buf, Fb>, buttons: &[Option<Button>],
+                      pressed: &mut Option<(usize, u16)>) {
+        if let Some((i, _)) = pressed.take() {
+            if let Some(b) = &buttons[i] {
+                b.set_highlight(gfx, false);
+            }
+        }
+    }
+
+    /// Send the pixels of a rectangle with (inclusive) coordinates (x1, y1) to
+    /// (x2, y2) from the graphics or console framebuffer back to the host.
+    /// While a frame is drawn off-screen, the displayed graphics are sent.
+    ///
+    /// Every reply contains the position of the first pixel and up to
+    /// `SCREENSHOT_CHUNK` pixels of a single row.  A reply without data
+    /// finishes the transfer.
+    fn send_screenshot(con: &mut Console<'buf, Tx, Fb>, gfx: &FrameBuffer<'buf, Fb>, console: bool,
+                       (x1, y1): (u16, u16), (x2, y2): (u16, u16)) {
+        let (width, height) = (gfx.width(), gfx.height());
+        let (x2, y2) = (x2.min(width - 1), y2.min(height - 1));
+        let mut reply = [0; 6 + SCREENSHOT_CHUNK];
+        for y in y1..=y2 {
+            for x in (x1..=x2).step_by(SCREENSHOT_CHUNK) {
+                let n = (x2 + 1 - x).min(SCREENSHOT_CHUNK as u16) as usize;
+                let start = x as usize + y as usize * width as usize;
+                let buf = if console { con.buf() } else { gfx.front_buf() };
+                let (b0, b1) = pos_to_bytes(x, y);
+                reply[..6].copy_from_slice(&[ESCAPE, ESCAPE, 3 + n as u8, CMD_SCREENSHOT, b0, b1]);
+                reply[6..6 + n].copy_from_slice(&buf[start..start + n]);
+                con.write_to_host(&reply[..6 + n]);
+            }
+        }
+        con.write_to_host(&[ESCAPE, ESCAPE, 0x01, CMD_SCREENSHOT]);
+    }
+
+    /// Send the reply to CAPABILITIES: display size, console columns and rows,
+    /// maximum command length and optional command bits, followed by the
+    /// number of fonts and their character sizes, and the number of
+    /// built-in images with their sizes and default palettes.
+    fn send_capabilities(con: &mut Console<'buf, Tx, Fb>, gfx: &FrameBuffer<'buf, Fb>) {
+        let mut caps = CAP_OVERLAY | CAP_ACK | CAP_CRC | CAP_STREAMING | CAP_RAM_IMAGES |
+            CAP_SCREENSHOT | CAP_WIDGETS | CAP_BUTTONS | CAP_TOUCH_EVENTS | CAP_CLUT |
+            CAP_BACKLIGHT | CAP_SAVER;
+        if gfx.has_back_buffer() {
+            caps |= CAP_FRAMES;
+        }
+        if !IMAGES.is_empty() {
+            caps |= CAP_SAVER_LOGO;
+        }
+        let len = 15 + 2 * FONTS.len() + 8 * IMAGES.len();
+        con.write_to_host(&[ESCAPE, ESCAPE, len as u8, CMD_CAPABILITIES]);
+        con.write_to_host(&gfx.width().to_be_bytes());
+        con.write_to_host(&gfx.height().to_be_bytes());
+        con.write_to_host(&[COLS as u8, ROWS as u8]);
+        con.write_to_host(&(MAX_CMD_LEN as u16).to_be_bytes());
+        con.write_to_host(&caps.to_be_bytes());
+        con.write_to_host(&[FONTS.len() as u8]);
+        for font in FONTS {
+            let (w, h) = font.size();
+            con.write_to_host(&[w as u8, h as u8]);
+        }
+        con.write_to_host(&[IMAGES.len() as u8]);
+        for (_, (w, h), pal) in IMAGES {
+            con.write_to_host(&w.to_be_bytes());
+            con.write_to_host(&h.to_be_bytes());
+            con.write_to_host(pal);
+        }
+    }
+
+    /// Send the acknowledgement for a processed command, if ack mode is on.
+    fn send_ack(con: &mut Console<'buf, Tx, Fb>, ack_seq: &mut Option<u8>, status: Status) {
+        if let Some(seq) = ack_seq {
+            con.write_to_host(&[ESCAPE, ESCAPE, 0x03, CMD_ACK, *seq, status as u8]);
+            *seq = seq.wrapping_add(1);
+        }
+    }
+
+    /// Start streaming a graphics command that doesn't fit into the escape
+    /// buffer.  The escape buffer contains the command and its header up to
+    /// `pos`, and the whole command has `len` bytes.
+    ///
+    /// Since the data is processed as it arrives, a CRC mismatch can only be
+    /// detected at the end, when the corrupted data has already been drawn or
+    /// stored.  Unlike other corrupted commands, which are dropped without
+    /// an ack, it is therefore acked with `Status::BadCrc`, so that the host
+    /// can send the data again.
+    ///
+    /// Other commands can't be this long; the length is probably corrupted,
+    /// so the command is rejected at once instead of skipping the following
+    /// bytes.
+    fn start_stream(&mut self, start: usize, pos: usize, len: usize) {
+        let cmd = self.escape_seq[start];
+        if stream_header_len(cmd) == 0 {
+            self.escape = Escape::None;
+            Self::send_ack(&mut self.con, &mut self.ack_seq, Status::BadLength);
+            return;
+        }
+        let header = &self.escape_seq[start + 1..pos];
+        self.stream_status = Status::Ok;
+        self.stream = match cmd {
+            CMD_IMAGE_DATA => match ram_image_slot(header[0]) {
+                Some(slot) => Stream::ImageData(slot, u16_from_bytes(&header[1..]) as usize),
+                None => {
+                    self.stream_status = Status::OutOfRange;
+                    Stream::Skip
+                }
+            },
+            _ => Stream::Pixels(PixelStream::new(cmd, header)),
+        };
+        self.stream_crc = (crc16(CRC_INIT, &self.escape_seq[..pos]), 0);
+        self.escape = Escape::Stream(pos, len);
+    }
+
+    fn stream_byte(&mut self, byte: u8) {
+        match self.stream {
+            Stream::Skip => {}
+            Stream::Pixels(ref mut pixels) => pixels.feed(&mut self.gfx, &self.cur.pal, byte),
+            Stream::ImageData(slot, ref mut offset) => {
+                if !self.images.write(slot, *offset, &[byte]) {
+                    self.stream_status = Status::OutOfRange;
+                    self.stream = Stream::Skip;
+                    return;
+                }
+                *offset += 1;
+            }
+        }
+    }
+
+    fn finish_stream(&mut self) {
+        if self.crc_mode && self.stream_crc.0 != self.stream_crc.1 {
+            self.stream_status = Status::BadCrc;
+        } else if let Stream::Pixels(ref mut pixels) = self.stream {
+            pixels.finish(&mut self.gfx, &self.cur.pal);
+        }
+        self.stream = Stream::Skip;
+        Self::send_ack(&mut self.con, &mut self.ack_seq, self.stream_status);
+    }
+
+    /// Process a complete graphics command in the escape buffer.  The length
+    /// byte preceding the command is at `start`, the command ends at `end`.
+    fn process_command(&mut self, start: usize, end: usize) -> Action<'_> {
+        let cmd = &self.escape_seq[start..end];
         let data_len = cmd.len() - 2;
+        let mut action = Action::None;
+        let mut status = Status::Ok;
         match cmd[1] {
             CMD_MODE_GRAPHICS => {
                 self.gfx_mode = true;
@@ -254,16 +863,42 @@ impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx,
                 self.gfx_mode = false;
                 self.con.activate();
             },
+            CMD_BEGIN_FRAME => {
+                self.gfx.begin_frame();
+            },
+            CMD_END_FRAME => {
+                // show the new frame on the next vsync
+                if self.gfx.end_frame() && self.gfx_mode {
+                    self.gfx.activate();
+                }
+            },
+            CMD_OVERLAY => if data_len >= 2 {
+                self.overlay.show(cmd[2], cmd[3], &cmd[4..]);
+                action = Action::ShowOverlay(self.overlay.buf());
+            } else if data_len == 0 {
+                self.overlay.hide();
+                action = Action::HideOverlay;
+            } else {
+                status = Status::BadLength;
+            },
             CMD_SET_POS => if data_len >= 2 {
                 let (x, y) = pos_from_bytes(&cmd[2..]);
                 self.cur.posx = x;
                 self.cur.posy = y;
+            } else {
+                status = Status::BadLength;
             },
-            CMD_SET_FONT => if data_len >= 1 && cmd[2] < FONTS.len() as u8 {
+            CMD_SET_FONT => if data_len < 1 {
+                status = Status::BadLength;
+            } else if cmd[2] < FONTS.len() as u8 {
                 self.cur.font = cmd[2];
+            } else {
+                status = Status::OutOfRange;
             },
             CMD_SET_COLOR => if data_len >= 4 {
                 self.cur.pal.copy_from_slice(&cmd[2..6]);
+            } else {
+                status = Status::BadLength;
             }
             CMD_SET_CLIP => {
                 if data_len >= 4 {
@@ -275,9 +910,71 @@ impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx,
                 }
                 self.gfx.set_clip(self.cur.clip1, self.cur.clip2);
             }
+            CMD_SET_ALIGN => match cmd.get(2) {
+                Some(1 | 2) if data_len < 3 => status = Status::BadLength,
+                Some(1) => self.cur.align = Align::Center(u16_from_bytes(&cmd[3..])),
+                Some(2) => self.cur.align = Align::Right(u16_from_bytes(&cmd[3..])),
+                _ => self.cur.align = Align::Left,
+            }
+            CMD_SET_CLUT => if data_len < 4 || data_len % 3 != 1 {
+                status = Status::BadLength;
+            } else if cmd[2] as usize + (data_len - 1) / 3 <= 256 {
+                action = Action::SetLut(cmd[2], &cmd[3..2+data_len]);
+            } else {
+                status = Status::OutOfRange;
+            }
+            CMD_RESET_CLUT => {
+                self.palette.clear();
+                action = Action::ResetLut;
+            }
+            CMD_ANIMATE_CLUT => if data_len == 1 {
+                self.palette.remove(cmd[2]);
+            } else if data_len < 6 {
+                status = Status::BadLength;
+            } else if !self.palette.set(cmd[2], u16_from_bytes(&cmd[3..]), &cmd[5..2+data_len]) {
+                status = Status::OutOfRange;
+            }
+            CMD_SET_BACKLIGHT => if data_len >= BACKLIGHT_SETTINGS_LEN {
+                let settings = &cmd[2..2+BACKLIGHT_SETTINGS_LEN];
+                self.backlight.configure(settings);
+                // only store the settings if requested, to spare the EEPROM
+                // when the host changes the brightness often
+                if cmd.get(2 + BACKLIGHT_SETTINGS_LEN).is_some_and(|&flags| flags & 1 != 0) {
+                    action = Action::WriteEeprom(EEPROM_BACKLIGHT.0, EEPROM_BACKLIGHT.1, settings);
+                }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_SET_SAVER => if data_len < 3 {
+                status = Status::BadLength;
+            } else if cmd[2] <= 2 {
+                let mode = [SaverMode::Off, SaverMode::Blank, SaverMode::Logo][cmd[2] as usize];
+                self.saver.configure(mode, u16_from_bytes(&cmd[3..]));
+            } else {
+                status = Status::OutOfRange;
+            }
             CMD_TEXT => {
-                self.gfx.text(&FONTS[self.cur.font as usize], self.cur.posx,
-                              self.cur.posy, &cmd[2..], &self.cur.pal);
+                self.gfx.text_aligned(&FONTS[self.cur.font as usize], self.cur.posx,
+                                      self.cur.posy, &cmd[2..], &self.cur.pal,
+                                      self.cur.align);
+            }
+            CMD_TEXT_BOX => if data_len >= 5 {
+                let pos1 = pos_from_bytes(&cmd[2..]);
+                let pos2 = pos_from_bytes(&cmd[4..]);
+                let ellipsis = cmd[6] & 1 != 0;
+                self.gfx.text_box(&FONTS[self.cur.font as usize], pos1.0, pos1.1, pos2.0, pos2.1,
+                                  &cmd[7..], &self.cur.pal, self.cur.align, ellipsis);
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_TEXT_SIZE => if data_len < 1 {
+                status = Status::BadLength;
+            } else if cmd[2] < FONTS.len() as u8 {
+                let (w, h) = FONTS[cmd[2] as usize].text_size(&cmd[3..]);
+                self.con.write_to_host(&[ESCAPE, ESCAPE, 0x05, CMD_TEXT_SIZE,
+                                         (w >> 8) as u8, w as u8, (h >> 8) as u8, h as u8]);
+            } else {
+                status = Status::OutOfRange;
             }
             CMD_LINES => if data_len >= 4 && data_len % 2 == 0 {
                 let mut pos1 = pos_from_bytes(&cmd[2..]);
@@ -286,49 +983,149 @@ impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx,
                     self.gfx.line(pos1.0, pos1.1, pos2.0, pos2.1, self.cur.pal[3]);
                     pos1 = pos2;
                 }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_POLYGON => if data_len >= 6 && data_len.is_multiple_of(2) {
+                let points = cmd[2..].chunks(2).map(pos_from_bytes);
+                self.gfx.fill_polygon(points, self.cur.pal[3]);
+            } else {
+                status = Status::BadLength;
             }
             CMD_RECT => if data_len >= 4 {
                 let pos1 = pos_from_bytes(&cmd[2..]);
                 let pos2 = pos_from_bytes(&cmd[4..]);
                 self.gfx.rect(pos1.0, pos1.1, pos2.0, pos2.1, self.cur.pal[3]);
+            } else {
+                status = Status::BadLength;
             }
-            CMD_IMAGE => if data_len >= 1 && cmd[2] < IMAGES.len() as u8 {
-                let (data, size, default_pal) = IMAGES[cmd[2] as usize];
-                let pal = if data_len >= 5 {
-                    [cmd[3], cmd[4], cmd[5], cmd[6]]
+            CMD_SCREENSHOT => if (2..5).contains(&data_len) {
+                status = Status::BadLength;
+            } else {
+                let console = data_len >= 1 && cmd[2] != 0;
+                let (pos1, pos2) = if data_len >= 5 {
+                    (pos_from_bytes(&cmd[3..]), pos_from_bytes(&cmd[5..]))
                 } else {
-                    default_pal
+                    ((0, 0), (self.gfx.width() - 1, self.gfx.height() - 1))
                 };
-                self.gfx.image(self.cur.posx, self.cur.posy, data, size, &pal);
+                Self::send_screenshot(&mut self.con, &self.gfx, console, pos1, pos2);
+            }
+            CMD_CIRCLE => if data_len >= 3 {
+                let (x, y) = pos_from_bytes(&cmd[2..]);
+                let r = cmd[4] as u16;
+                if data_len >= 4 && cmd[5] != 0 {
+                    self.gfx.fill_circle(x, y, r, self.cur.pal[3]);
+                } else {
+                    self.gfx.circle(x, y, r, self.cur.pal[3]);
+                }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_ELLIPSE => if data_len >= 4 {
+                let (x, y) = pos_from_bytes(&cmd[2..]);
+                let (rx, ry) = pos_from_bytes(&cmd[4..]);
+                if data_len >= 5 && cmd[6] != 0 {
+                    self.gfx.fill_ellipse(x, y, rx, ry, self.cur.pal[3]);
+                } else {
+                    self.gfx.ellipse(x, y, rx, ry, self.cur.pal[3]);
+                }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_ARC => if data_len >= 7 {
+                let (x, y) = pos_from_bytes(&cmd[2..]);
+                let r = cmd[4] as u16;
+                let start = u16_from_bytes(&cmd[5..]);
+                let end = u16_from_bytes(&cmd[7..]);
+                if data_len >= 8 && cmd[9] != 0 {
+                    self.gfx.fill_arc(x, y, r, start, end, self.cur.pal[3]);
+                } else {
+                    self.gfx.arc(x, y, r, start, end, self.cur.pal[3]);
+                }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_RECT_OUTLINE => if data_len >= 4 {
+                let pos1 = pos_from_bytes(&cmd[2..]);
+                let pos2 = pos_from_bytes(&cmd[4..]);
+                let radius = if data_len >= 5 { cmd[6] as u16 } else { 0 };
+                let thickness = if data_len >= 6 { cmd[7] as u16 } else { 1 };
+                self.gfx.round_rect_outline(pos1.0, pos1.1, pos2.0, pos2.1,
+                                            radius, thickness, self.cur.pal[3]);
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_ROUND_RECT => if data_len >= 5 {
+                let pos1 = pos_from_bytes(&cmd[2..]);
+                let pos2 = pos_from_bytes(&cmd[4..]);
+                self.gfx.round_rect(pos1.0, pos1.1, pos2.0, pos2.1, cmd[6] as u16,
+                                    self.cur.pal[3]);
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_IMAGE => if data_len >= 1 && !(2..5).contains(&data_len) {
+                let image = if let Some(slot) = ram_image_slot(cmd[2]) {
+                    self.images.get(slot)
+                } else {
+                    IMAGES.get(cmd[2] as usize).copied()
+                };
+                if let Some((data, size, default_pal)) = image {
+                    let pal = if data_len >= 5 {
+                        [cmd[3], cmd[4], cmd[5], cmd[6]]
+                    } else {
+                        default_pal
+                    };
+                    self.gfx.image(self.cur.posx, self.cur.posy, data, size, &pal);
+                } else {
+                    status = Status::OutOfRange;
+                }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_IMAGE_DEFINE => if data_len >= 7 {
+                let defined = ram_image_slot(cmd[2]).is_some_and(|slot| {
+                    let size = pos_from_bytes(&cmd[3..]);
+                    self.images.define(slot, size, [cmd[5], cmd[6], cmd[7], cmd[8]])
+                });
+                if !defined {
+                    status = Status::OutOfRange;
+                }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_IMAGE_DATA => if data_len >= 3 {
+                let written = ram_image_slot(cmd[2]).is_some_and(|slot| {
+                    let offset = u16_from_bytes(&cmd[3..]) as usize;
+                    self.images.write(slot, offset, &cmd[5..])
+                });
+                if !written {
+                    status = Status::OutOfRange;
+                }
+            } else {
+                status = Status::BadLength;
             }
             CMD_CLEAR => if data_len >= 1 {
                 self.gfx.clear(cmd[2]);
+            } else {
+                status = Status::BadLength;
             }
             CMD_COPYRECT => if data_len >= 6 {
                 let pos1 = pos_from_bytes(&cmd[2..]);
                 let pos2 = pos_from_bytes(&cmd[4..]);
                 let pos3 = pos_from_bytes(&cmd[6..]);
                 self.gfx.copy_rect(pos1.0, pos1.1, pos2.0, pos2.1, pos3.0, pos3.1);
+            } else {
+                status = Status::BadLength;
             }
-            CMD_PIXELS => if data_len >= 6 {
-                let (x0, y0) = pos_from_bytes(&cmd[2..]);
-                let (nx, ny) = pos_from_bytes(&cmd[4..]);
-                let (sx, sy) = pos_from_bytes(&cmd[6..]);
-                let (sx, sy) = (sx.max(1), sy.max(1));
-                let (dx, dy) = (nx.saturating_mul(sx), ny.saturating_mul(sy));
-                let mut colors = cmd[8..].iter();
-                let fg_color = self.cur.pal[3];
-                let use_rects = sx > 1 || sy > 1;
-                for y in (y0..y0 + dy).step_by(sy as _) {
-                    for x in (x0..x0 + dx).step_by(sx as _) {
-                        let color = *colors.next().unwrap_or(&fg_color);
-                        if use_rects {
-                            self.gfx.rect(x, y, x + sx - 1, y + sy - 1, color);
-                        } else {
-                            self.gfx.set_pixel(x, y, color);
-                        }
-                    }
+            CMD_PIXELS | CMD_PIXELS_1BPP | CMD_PIXELS_2BPP | CMD_PIXELS_4BPP |
+            CMD_PIXELS_RLE => if data_len >= 6 {
+                let mut pixels = PixelStream::new(cmd[1], &cmd[2..8]);
+                for &byte in &cmd[8..] {
+                    pixels.feed(&mut self.gfx, &self.cur.pal, byte);
                 }
+                pixels.finish(&mut self.gfx, &self.cur.pal);
+            } else {
+                status = Status::BadLength;
             }
             CMD_PLOT => if data_len >= 3 {
                 // Extended plot command that can handle a range of y values for
@@ -375,6 +1172,105 @@ impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx,
                     }
                     x += 1;
                 }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_BAR_DEFINE => if data_len >= 15 {
+                let bar = Bar {
+                    pos1: pos_from_bytes(&cmd[3..]),
+                    pos2: pos_from_bytes(&cmd[5..]),
+                    min: i16_from_bytes(&cmd[7..]),
+                    max: i16_from_bytes(&cmd[9..]),
+                    value: i16_from_bytes(&cmd[11..]),
+                    vertical: cmd[13] & 1 != 0,
+                    label: cmd[13] & 2 != 0,
+                    ticks: cmd[14],
+                    empty: cmd[15],
+                    fill: cmd[16],
+                    font: self.cur.font,
+                    pal: self.cur.pal,
+                };
+                if (cmd[2] as usize) < MAX_BARS && bar.is_valid() {
+                    bar.draw(&mut self.gfx);
+                    self.bars[cmd[2] as usize] = Some(bar);
+                } else {
+                    status = Status::OutOfRange;
+                }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_BAR_VALUE => if data_len < 3 {
+                status = Status::BadLength;
+            } else if let Some(Some(bar)) = self.bars.get_mut(cmd[2] as usize) {
+                bar.update(&mut self.gfx, i16_from_bytes(&cmd[3..]));
+            } else {
+                status = Status::OutOfRange;
+            }
+            CMD_LABEL_DEFINE => if data_len >= 5 {
+                let label = Label {
+                    pos1: pos_from_bytes(&cmd[3..]),
+                    pos2: pos_from_bytes(&cmd[5..]),
+                    font: self.cur.font,
+                    pal: self.cur.pal,
+                    align: self.cur.align,
+                };
+                if (cmd[2] as usize) < MAX_LABELS && label.is_valid() {
+                    label.set_text(&mut self.gfx, &cmd[7..]);
+                    self.labels[cmd[2] as usize] = Some(label);
+                } else {
+                    status = Status::OutOfRange;
+                }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_LABEL_TEXT => if data_len < 1 {
+                status = Status::BadLength;
+            } else if let Some(Some(label)) = self.labels.get(cmd[2] as usize) {
+                label.set_text(&mut self.gfx, &cmd[3..]);
+            } else {
+                status = Status::OutOfRange;
+            }
+            CMD_CHART_DEFINE => if data_len >= 11 {
+                let ncolors = (data_len - 10).min(MAX_CHANNELS);
+                let mut colors = [0; MAX_CHANNELS];
+                colors[..ncolors].copy_from_slice(&cmd[12..12+ncolors]);
+                let chart = Chart {
+                    pos1: pos_from_bytes(&cmd[3..]),
+                    pos2: pos_from_bytes(&cmd[5..]),
+                    min: i16_from_bytes(&cmd[7..]),
+                    max: i16_from_bytes(&cmd[9..]),
+                    axes: cmd[11] & 1 != 0,
+                    autoscale: cmd[11] & 2 != 0,
+                    channels: ncolors as u8,
+                    colors,
+                    font: self.cur.font,
+                    pal: self.cur.pal,
+                    samples: Samples::default(),
+                };
+                if (cmd[2] as usize) < MAX_CHARTS && chart.is_valid() {
+                    chart.draw(&mut self.gfx);
+                    self.charts[cmd[2] as usize] = Some(chart);
+                } else {
+                    status = Status::OutOfRange;
+                }
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_CHART_PUSH => if data_len < 4 || !data_len.is_multiple_of(2) {
+                status = Status::BadLength;
+            } else if let Some(Some(chart)) = self.charts.get_mut(cmd[2] as usize) {
+                // further values go to the following channels
+                let first = cmd[3] as usize;
+                let values = cmd[4..2+data_len].chunks_exact(2);
+                if first + values.len() <= chart.channels as usize {
+                    for (ch, value) in (first..).zip(values) {
+                        chart.push(&mut self.gfx, ch, i16_from_bytes(value));
+                    }
+                } else {
+                    status = Status::OutOfRange;
+                }
+            } else {
+                status = Status::OutOfRange;
             }
             CMD_SEL_ATTRS ..= CMD_SEL_ATTRS_MAX => {
                 self.cur = self.saved[(cmd[1] - CMD_SEL_ATTRS) as usize];
@@ -383,34 +1279,91 @@ impl<'buf, Tx: WriteToHost, Th: TouchHandler, Fb: FbImpl> DisplayState<'buf, Tx,
             CMD_SAVE_ATTRS ..= CMD_SAVE_ATTRS_MAX => {
                 self.saved[(cmd[1] - CMD_SAVE_ATTRS) as usize] = self.cur;
             }
-            CMD_BOOTMODE => if data_len >= 4 && cmd[2..6] == crate::FW_IDENT[..4] {
-                return Action::Bootloader;
-            },
-            CMD_RESET => if data_len >= 4 && cmd[2..6] == crate::FW_IDENT[..4] {
-                return Action::Reset;
-            },
-            CMD_RESET_APU => if data_len >= 4 && cmd[2..6] == crate::FW_IDENT[..4] {
-                return Action::ResetApu;
-            },
-            CMD_APU_REINSTALL => if data_len >= 4 && cmd[2..6] == crate::FW_IDENT[..4] {
-                return Action::ApuReinstall;
+            CMD_BOOTMODE | CMD_RESET | CMD_RESET_APU | CMD_APU_REINSTALL => if data_len < 4 {
+                status = Status::BadLength;
+            } else if cmd[2..6] == crate::FW_IDENT[..4] {
+                action = match cmd[1] {
+                    CMD_BOOTMODE => Action::Bootloader,
+                    CMD_RESET => Action::Reset,
+                    CMD_RESET_APU => Action::ResetApu,
+                    _ => Action::ApuReinstall,
+                };
+            } else {
+                status = Status::OutOfRange;
             },
             CMD_SET_STARTUP => {
-                return Action::WriteEeprom(0, 64, &cmd[2..]);
+                action = Action::WriteEeprom(0, 64, &cmd[2..]);
             }
             CMD_TOUCH_MODE => if data_len >= 1 {
-                self.fwd_touch = cmd[2] > 0;
+                self.touch_mode = match cmd[2] {
+                    0 => TouchMode::Toggle,
+                    2 => TouchMode::Events,
+                    _ => TouchMode::Forward,
+                };
+                self.touch_start = None;
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_BUTTON_DEFINE => if data_len != 1 && data_len < 5 {
+                status = Status::BadLength;
+            } else if (cmd[2] as usize) < MAX_BUTTONS {
+                let i = cmd[2] as usize;
+                // a highlight belongs to the old definition
+                if self.pressed.is_some_and(|(j, _)| j == i) {
+                    Self::release_button(&mut self.gfx, &self.buttons, &mut self.pressed);
+                }
+                // without region, the button is removed
+                self.buttons[i] = None;
+                if data_len >= 5 {
+                    let button = Button {
+                        pos1: pos_from_bytes(&cmd[3..]),
+                        pos2: pos_from_bytes(&cmd[5..]),
+                        pal: self.cur.pal,
+                        pressed: (data_len >= 9).then(|| [cmd[7], cmd[8], cmd[9], cmd[10]]),
+                    };
+                    if button.is_valid() {
+                        self.buttons[i] = Some(button);
+                    } else {
+                        status = Status::OutOfRange;
+                    }
+                }
+            } else {
+                status = Status::OutOfRange;
             }
-            CMD_TOUCH_CALIB => if data_len >= 4 {
+            CMD_TOUCH_CALIB => if data_len < 4 {
+                status = Status::BadLength;
+            } else if cmd[2] > 0 && cmd[4] > 0 {
                 self.touch.set_calib((cmd[2] as u16, cmd[3] as u16,
                                       cmd[4] as u16, cmd[5] as u16));
+                action = Action::WriteEeprom(EEPROM_TOUCH_CALIB.0, EEPROM_TOUCH_CALIB.1, &cmd[2..6]);
+            } else {
+                status = Status::OutOfRange;
+            }
+            CMD_TOUCH_CALIBRATE => {
+                // the calibration is drawn in the back buffer, which is in
+                // use while a frame is drawn
+                self.gfx.end_frame();
+                action = Action::CalibrateTouch;
+            }
+            CMD_ACK_MODE => if data_len >= 1 {
+                self.ack_seq = (cmd[2] > 0).then_some(0);
+            } else {
+                status = Status::BadLength;
+            }
+            CMD_CRC_MODE => if data_len >= 1 {
+                self.crc_mode = cmd[2] > 0;
+                self.con.write_to_host(&[ESCAPE, ESCAPE, 0x02, CMD_CRC_MODE, self.crc_mode as u8]);
+            } else {
+                status = Status::BadLength;
             }
             CMD_IDENT => {
                 self.con.write_to_host(&[0x1b, 0x1b, 0x05, 0xf3]);
                 self.con.write_to_host(&crate::FW_IDENT[4..]);
             }
-            _ => {}
+            CMD_CAPABILITIES => Self::send_capabilities(&mut self.con, &self.gfx),
+            _ => status = Status::UnknownCommand,
         }
-        Action::None
+        Self::send_ack(&mut self.con, &mut self.ack_seq, status);
+        action
     }
 }
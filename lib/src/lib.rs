@@ -2,10 +2,15 @@
 
 use pkg_version::*;
 
-mod image;
+pub mod image;
 pub mod interface;
 pub mod framebuf;
 pub mod console;
+pub mod overlay;
+pub mod backlight;
+pub mod palette;
+pub mod screensaver;
+pub mod widget;
 
 /// Width and height of visible screen.
 pub const WIDTH: u16 = 480;
@@ -79,7 +84,29 @@ const MODE: u8 = 1;
 /// - 1.25: retry DHCP request in display-initiated PXE boot
 /// - 1.26: FAU customer
 /// - 1.27: work extra hard to catch prompt in PXE boot
-/// - 1.28: new PIXELS command
+/// - 1.28: new PIXELS command, new CIRCLE, ELLIPSE and ARC commands,
+///   new POLYGON command, new RECT_OUTLINE and ROUND_RECT commands,
+///   new SET_ALIGN, TEXT_SIZE and TEXT_BOX commands,
+///   new IMAGE_DEFINE and IMAGE_DATA commands for images in RAM,
+///   new packed and run-length encoded PIXELS commands, new SCREENSHOT command,
+///   new ACK_MODE command for command acknowledgements,
+///   new CRC_MODE command for CRC-protected framing,
+///   extended framing with 16-bit length and streaming of bulk data,
+///   new BEGIN_FRAME and END_FRAME commands for off-screen drawing,
+///   new OVERLAY command for notifications,
+///   new BAR_DEFINE and BAR_VALUE commands for bar graph widgets,
+///   new LABEL_DEFINE and LABEL_TEXT commands for retained labels,
+///   new CHART_DEFINE and CHART_PUSH commands for scrolling strip charts,
+///   new BUTTON_DEFINE command for touch buttons,
+///   touch events report the y coordinate, TOUCH_CALIB calibrates both axes
+///   and is stored in the EEPROM, new TOUCH_CALIBRATE command for interactive
+///   touch calibration, touch mode 2 for press, release, long-press and swipe
+///   events, new SET_CLUT and RESET_CLUT commands to change the color table,
+///   new ANIMATE_CLUT command for blinking and cycling colors,
+///   new SET_BACKLIGHT command for brightness and automatic dimming, which
+///   are optionally stored in the EEPROM,
+///   new SET_SAVER command for blanking or a moving logo after inactivity,
+///   new CAPABILITIES command to query display, font and image properties
 pub const VER_MAJOR: u8 = pkg_version_major!();
 pub const VER_MINOR: u8 = pkg_version_minor!();
 
@@ -0,0 +1,78 @@
+//! Notification overlay, shown on top of the graphics or console display.
+
+use crate::WIDTH;
+use crate::framebuf::{FrameBuffer, FbImpl, Align, MEDIUMFONT};
+
+/// Size of the overlay window.
+pub const OVERLAY_WIDTH: u16 = 320;
+pub const OVERLAY_HEIGHT: u16 = 20;
+/// Top-left corner of the overlay window on screen.
+pub const OVERLAY_POS: (u16, u16) = ((WIDTH - OVERLAY_WIDTH) / 2, 4);
+/// Constant alpha for blending the overlay window over the display.
+pub const OVERLAY_ALPHA: u8 = 0xe0;
+
+/// Background and text colors for the severity levels info, warning, error.
+const SEVERITY_COLORS: [(u8, u8); 3] = [(4, 15), (11, 0), (1, 15)];
+
+pub struct Overlay<'buf, Fb> {
+    fb: FrameBuffer<'buf, Fb>,
+    visible: bool,
+    // time left until the overlay is hidden, in milliseconds
+    timeout: Option<u32>,
+}
+
+impl<'buf, Fb: FbImpl> Overlay<'buf, Fb> {
+    /// Create a new overlay.  The buffer must hold `OVERLAY_WIDTH` x
+    /// `OVERLAY_HEIGHT` pixels.
+    pub fn new(buf: &'buf mut [u8], impls: Fb) -> Self {
+        Self { fb: FrameBuffer::new(buf, OVERLAY_WIDTH, OVERLAY_HEIGHT, impls),
+               visible: false, timeout: None }
+    }
+
+    pub fn buf(&self) -> &[u8] {
+        self.fb.buf()
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Draw a message with the given severity (0-2) into the overlay and mark
+    /// it as visible.  If the timeout (in seconds) is nonzero, the overlay is
+    /// hidden again after it elapses.
+    pub fn show(&mut self, severity: u8, timeout: u8, text: &[u8]) {
+        let (bg, fg) = SEVERITY_COLORS[(severity as usize).min(SEVERITY_COLORS.len() - 1)];
+        let (w, h) = (OVERLAY_WIDTH - 1, OVERLAY_HEIGHT - 1);
+        // one pixel border in the text color
+        self.fb.clear(fg);
+        self.fb.rect(1, 1, w - 1, h - 1, bg);
+        let font = MEDIUMFONT;
+        let ty = (OVERLAY_HEIGHT - font.size().1) / 2;
+        self.fb.set_clip((1, 1), (w - 1, h - 1));
+        self.fb.text_aligned(font, 0, ty, text, &[bg, bg, fg, fg], Align::Center(OVERLAY_WIDTH));
+        self.fb.set_clip((0, 0), (w, h));
+        self.visible = true;
+        self.timeout = (timeout > 0).then_some(timeout as u32 * 1000);
+    }
+
+    pub fn hide(&mut self) {
+        self.visible = false;
+        self.timeout = None;
+    }
+
+    /// Advance the timeout by the given number of milliseconds.  Returns true
+    /// if the overlay has been hidden.
+    pub fn tick(&mut self, ms: u16) -> bool {
+        match self.timeout {
+            Some(left) if left <= ms as u32 => {
+                self.hide();
+                true
+            }
+            Some(left) => {
+                self.timeout = Some(left - ms as u32);
+                false
+            }
+            None => false,
+        }
+    }
+}
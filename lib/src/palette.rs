@@ -0,0 +1,105 @@
+//! Animation of color table entries, for blinking and pulsing colors.
+
+/// Maximum number of animated color table entries.
+pub const MAX_ANIMATIONS: usize = 16;
+
+/// Maximum number of colors an entry cycles through.
+pub const MAX_ANIMATION_COLORS: usize = 8;
+
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Clone, Copy)]
+struct Animation {
+    index: u8,
+    // time for one cycle through all colors, in milliseconds
+    period: u16,
+    ncolors: u8,
+    colors: [Rgb; MAX_ANIMATION_COLORS],
+    // index of the currently shown color
+    step: u8,
+    // if true, the color has changed and was not written to the table yet
+    dirty: bool,
+}
+
+impl Animation {
+    fn step_at(&self, time: u32) -> u8 {
+        ((time % self.period as u32) * self.ncolors as u32 / self.period as u32) as u8
+    }
+}
+
+pub struct PaletteAnimator {
+    entries: [Option<Animation>; MAX_ANIMATIONS],
+    // time since start, common to all animations to keep them in sync
+    time: u32,
+    // changed entries that are returned from `take_updates`
+    updates: [(u8, Rgb); MAX_ANIMATIONS],
+}
+
+impl Default for PaletteAnimator {
+    fn default() -> Self {
+        Self { entries: [None; MAX_ANIMATIONS], time: 0, updates: [(0, (0, 0, 0)); MAX_ANIMATIONS] }
+    }
+}
+
+impl PaletteAnimator {
+    /// Animate the table entry `index`, cycling through the given RGB colors
+    /// with the given period in milliseconds.  Replaces an existing animation
+    /// of the same entry.  Returns false if the colors or period are invalid,
+    /// or if too many entries are animated.
+    pub fn set(&mut self, index: u8, period: u16, rgb: &[u8]) -> bool {
+        let ncolors = rgb.len() / 3;
+        if ncolors == 0 || ncolors > MAX_ANIMATION_COLORS || ncolors * 3 != rgb.len() || period == 0 {
+            return false;
+        }
+        self.remove(index);
+        let Some(slot) = self.entries.iter_mut().find(|e| e.is_none()) else { return false };
+        let mut colors = [(0, 0, 0); MAX_ANIMATION_COLORS];
+        for (color, c) in colors.iter_mut().zip(rgb.chunks_exact(3)) {
+            *color = (c[0], c[1], c[2]);
+        }
+        let mut anim = Animation { index, period, ncolors: ncolors as u8, colors, step: 0, dirty: true };
+        anim.step = anim.step_at(self.time);
+        *slot = Some(anim);
+        true
+    }
+
+    /// Stop animating the table entry `index`.  It keeps its current color.
+    pub fn remove(&mut self, index: u8) {
+        for entry in &mut self.entries {
+            if entry.is_some_and(|a| a.index == index) {
+                *entry = None;
+            }
+        }
+    }
+
+    /// Stop all animations.
+    pub fn clear(&mut self) {
+        self.entries = [None; MAX_ANIMATIONS];
+    }
+
+    /// Advance the time by the given number of milliseconds.
+    pub fn tick(&mut self, ms: u16) {
+        self.time = self.time.wrapping_add(ms as u32);
+        for anim in self.entries.iter_mut().flatten() {
+            let step = anim.step_at(self.time);
+            if step != anim.step {
+                anim.step = step;
+                anim.dirty = true;
+            }
+        }
+    }
+
+    /// Return the entries whose color changed since the last call, as
+    /// (index, color) pairs.
+    pub fn take_updates(&mut self) -> &[(u8, Rgb)] {
+        let mut n = 0;
+        for anim in self.entries.iter_mut().flatten() {
+            if anim.dirty {
+                anim.dirty = false;
+                self.updates[n] = (anim.index, anim.colors[anim.step as usize]);
+                n += 1;
+            }
+        }
+        &self.updates[..n]
+    }
+}
@@ -0,0 +1,121 @@
+//! Screen saver against burn-in, started after a period of host inactivity.
+
+use crate::image::IMAGES;
+use crate::framebuf::{FrameBuffer, FbImpl};
+
+/// Time between moves of the logo, in milliseconds.
+const LOGO_MOVE_MS: u32 = 1000;
+/// Distance of each move of the logo, in pixels.
+const LOGO_STEP: (i16, i16) = (16, 8);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SaverMode {
+    Off,
+    /// Switch off the display.
+    Blank,
+    /// Show the logo (image #0) moving around on a black screen, if available.
+    Logo,
+}
+
+pub struct ScreenSaver {
+    mode: SaverMode,
+    // time without activity before starting, in milliseconds
+    timeout: u32,
+    idle: u32,
+    active: bool,
+    // if true, the logo is shown in the swapped graphics buffers, else the
+    // display is blanked
+    swapped: bool,
+    // if true, the blanking has changed and was not applied yet
+    changed: bool,
+    // position and direction of the logo
+    pos: (i16, i16),
+    dir: (i16, i16),
+}
+
+impl Default for ScreenSaver {
+    fn default() -> Self {
+        Self { mode: SaverMode::Off, timeout: 0, idle: 0, active: false, swapped: false,
+               changed: false, pos: (0, 0), dir: (1, 1) }
+    }
+}
+
+impl ScreenSaver {
+    /// Set the mode and the timeout in seconds.  The saver must not be active.
+    pub fn configure(&mut self, mode: SaverMode, timeout: u16) {
+        self.mode = mode;
+        self.timeout = timeout as u32 * 1000;
+        self.idle = 0;
+    }
+
+    /// Register activity.  If the saver is active, it is stopped and the
+    /// graphics buffers are restored; the caller must then reactivate the
+    /// previously shown framebuffer.  Returns true if the saver was active.
+    pub fn activity<Fb: FbImpl>(&mut self, gfx: &mut FrameBuffer<'_, Fb>) -> bool {
+        self.idle = 0;
+        if !self.active {
+            return false;
+        }
+        self.active = false;
+        if self.swapped {
+            gfx.swap_back_buffer();
+            self.swapped = false;
+        } else {
+            self.changed = true;
+        }
+        true
+    }
+
+    /// Advance the idle time by the given number of milliseconds, and start
+    /// or update the saver.  Drawing the logo ignores and resets the clip
+    /// rectangle.
+    pub fn tick<Fb: FbImpl>(&mut self, gfx: &mut FrameBuffer<'_, Fb>, ms: u16) {
+        if self.mode == SaverMode::Off || self.timeout == 0 {
+            return;
+        }
+        self.idle = self.idle.saturating_add(ms as u32);
+        if !self.active {
+            if self.idle < self.timeout {
+                return;
+            }
+            self.active = true;
+            self.swapped = self.mode == SaverMode::Logo && !IMAGES.is_empty() &&
+                gfx.swap_back_buffer();
+            if !self.swapped {
+                self.changed = true;
+                return;
+            }
+        } else if !self.swapped || self.idle - self.timeout < LOGO_MOVE_MS {
+            return;
+        } else {
+            self.idle = self.timeout;
+            self.move_logo(gfx);
+        }
+        let (data, size, pal) = IMAGES[0];
+        gfx.set_clip((0, 0), (gfx.width() - 1, gfx.height() - 1));
+        gfx.clear(0);
+        gfx.image(self.pos.0 as u16, self.pos.1 as u16, data, size, &pal);
+        gfx.activate();
+    }
+
+    /// Move the logo by one step, bouncing off the edges.
+    fn move_logo<Fb: FbImpl>(&mut self, gfx: &FrameBuffer<'_, Fb>) {
+        let (_, (w, h), _) = IMAGES[0];
+        let max = (gfx.width().saturating_sub(w) as i16, gfx.height().saturating_sub(h) as i16);
+        let bounce = |pos: &mut i16, dir: &mut i16, step: i16, max: i16| {
+            let next = *pos + *dir * step;
+            if !(0..=max).contains(&next) {
+                *dir = -*dir;
+            }
+            *pos = (*pos + *dir * step).clamp(0, max);
+        };
+        bounce(&mut self.pos.0, &mut self.dir.0, LOGO_STEP.0, max.0);
+        bounce(&mut self.pos.1, &mut self.dir.1, LOGO_STEP.1, max.1);
+    }
+
+    /// Return whether the display should be blanked, if this has changed
+    /// since the last call.
+    pub fn take_blank_change(&mut self) -> Option<bool> {
+        core::mem::replace(&mut self.changed, false).then_some(self.active && !self.swapped)
+    }
+}
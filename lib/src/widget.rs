@@ -0,0 +1,432 @@
+//! Widgets that are defined once by the host and then updated by ID.
+
+use crate::{WIDTH, HEIGHT};
+use crate::interface::Palette;
+use crate::framebuf::{FrameBuffer, FbImpl, Align, FONTS};
+
+/// Maximum number of bar widgets.
+pub const MAX_BARS: usize = 16;
+
+/// Maximum number of label widgets.
+pub const MAX_LABELS: usize = 32;
+
+/// Maximum number of touch buttons.
+pub const MAX_BUTTONS: usize = 32;
+
+/// Maximum number of strip chart widgets.
+pub const MAX_CHARTS: usize = 2;
+
+/// Maximum number of channels (lines) in a strip chart.
+pub const MAX_CHANNELS: usize = 4;
+
+/// Number of samples retained per channel, enough for a chart over the full width.
+const MAX_SAMPLES: usize = WIDTH as usize;
+
+/// Length of the tick marks next to a bar.
+const TICK_LEN: u16 = 4;
+
+/// A bar graph or progress bar, with optional tick marks and value label.
+///
+/// Horizontal bars grow to the right and have ticks below and the label at the
+/// right; vertical bars grow upwards and have ticks at the right and the label
+/// below.
+#[derive(Clone, Copy)]
+pub struct Bar {
+    pub pos1: (u16, u16),
+    pub pos2: (u16, u16),
+    pub min: i16,
+    pub max: i16,
+    pub value: i16,
+    pub vertical: bool,
+    pub label: bool,
+    pub ticks: u8,
+    // colors of the bar's empty and filled parts
+    pub empty: u8,
+    pub fill: u8,
+    // font and palette for ticks and label
+    pub font: u8,
+    pub pal: Palette,
+}
+
+impl Bar {
+    /// Check that the widget's rectangle and range are not empty.
+    pub fn is_valid(&self) -> bool {
+        self.pos1.0 <= self.pos2.0 && self.pos1.1 <= self.pos2.1 && self.min < self.max
+    }
+
+    /// Draw the whole widget.
+    pub fn draw<Fb: FbImpl>(&self, gfx: &mut FrameBuffer<'_, Fb>) {
+        gfx.rect(self.pos1.0, self.pos1.1, self.pos2.0, self.pos2.1, self.pal[0]);
+        let (span, ..) = self.bar_rect();
+        let len = self.fill_len(self.value);
+        self.draw_part(gfx, 0, len, self.fill);
+        self.draw_part(gfx, len, span, self.empty);
+        if self.shows_ticks() {
+            self.draw_ticks(gfx);
+        }
+        if self.shows_label() {
+            self.draw_label(gfx);
+        }
+    }
+
+    /// Set a new value and redraw only the changed part of the bar.
+    pub fn update<Fb: FbImpl>(&mut self, gfx: &mut FrameBuffer<'_, Fb>, value: i16) {
+        let old = self.fill_len(self.value);
+        let new = self.fill_len(value);
+        if new > old {
+            self.draw_part(gfx, old, new, self.fill);
+        } else if new < old {
+            self.draw_part(gfx, new, old, self.empty);
+        }
+        self.value = value;
+        if self.shows_label() {
+            self.draw_label(gfx);
+        }
+    }
+
+    /// Return the length of the bar along its axis, and its (inclusive)
+    /// rectangle without ticks and label.
+    fn bar_rect(&self) -> (u16, (u16, u16), (u16, u16)) {
+        let (x1, y1) = self.pos1;
+        let (mut x2, mut y2) = self.pos2;
+        let reserve = |end: &mut u16, start: u16, len: u16| *end = end.saturating_sub(len).max(start);
+        if self.shows_label() {
+            let (w, h) = self.label_size();
+            if self.vertical { reserve(&mut y2, y1, h) } else { reserve(&mut x2, x1, w) }
+        }
+        if self.shows_ticks() {
+            if self.vertical {
+                reserve(&mut x2, x1, TICK_LEN + 1);
+            } else {
+                reserve(&mut y2, y1, TICK_LEN + 1);
+            }
+        }
+        let span = if self.vertical { y2 - y1 + 1 } else { x2 - x1 + 1 };
+        (span, (x1, y1), (x2, y2))
+    }
+
+    /// Check that ticks are enabled and fit beside the bar.
+    fn shows_ticks(&self) -> bool {
+        let (w, h) = self.size();
+        self.ticks > 0 && if self.vertical { w > TICK_LEN + 1 } else { h > TICK_LEN + 1 }
+    }
+
+    /// Check that the label is enabled and fits beside the bar.
+    fn shows_label(&self) -> bool {
+        let (w, h) = self.size();
+        let (lw, lh) = self.label_size();
+        self.label && if self.vertical { w + 2 >= lw && h > lh } else { h + 2 >= lh && w > lw }
+    }
+
+    /// Size of the widget's rectangle.
+    fn size(&self) -> (u16, u16) {
+        (self.pos2.0 - self.pos1.0 + 1, self.pos2.1 - self.pos1.1 + 1)
+    }
+
+    /// Size reserved for the label, including a gap to the bar.
+    fn label_size(&self) -> (u16, u16) {
+        let (cw, ch) = FONTS[self.font as usize].size();
+        let chars = format_value(self.min, &mut [0; 6]).len()
+            .max(format_value(self.max, &mut [0; 6]).len());
+        (cw * chars as u16 + 2, ch + 2)
+    }
+
+    /// Return the filled length of the bar for the given value.
+    fn fill_len(&self, value: i16) -> u16 {
+        let (span, ..) = self.bar_rect();
+        let value = value.clamp(self.min, self.max);
+        ((value as i32 - self.min as i32) * span as i32 /
+         (self.max as i32 - self.min as i32)) as u16
+    }
+
+    /// Draw the part of the bar from `from` up to `to` along its axis.
+    fn draw_part<Fb: FbImpl>(&self, gfx: &mut FrameBuffer<'_, Fb>, from: u16, to: u16, color: u8) {
+        if from >= to {
+            return;
+        }
+        let (_, (x1, y1), (x2, y2)) = self.bar_rect();
+        if self.vertical {
+            gfx.rect(x1, y2 + 1 - to, x2, y2 - from, color);
+        } else {
+            gfx.rect(x1 + from, y1, x1 + to - 1, y2, color);
+        }
+    }
+
+    fn draw_ticks<Fb: FbImpl>(&self, gfx: &mut FrameBuffer<'_, Fb>) {
+        let (span, (x1, _), (x2, y2)) = self.bar_rect();
+        let n = self.ticks.max(2) as u16;
+        for i in 0..n {
+            let offset = i * (span - 1) / (n - 1);
+            if self.vertical {
+                gfx.line(x2 + 2, y2 - offset, x2 + 1 + TICK_LEN, y2 - offset, self.pal[3]);
+            } else {
+                gfx.line(x1 + offset, y2 + 2, x1 + offset, y2 + 1 + TICK_LEN, self.pal[3]);
+            }
+        }
+    }
+
+    fn draw_label<Fb: FbImpl>(&self, gfx: &mut FrameBuffer<'_, Fb>) {
+        let font = &FONTS[self.font as usize];
+        let (w, h) = self.label_size();
+        let (x1, y1) = if self.vertical {
+            (self.pos1.0, (self.pos2.1 + 3).saturating_sub(h).max(self.pos1.1))
+        } else {
+            ((self.pos2.0 + 3).saturating_sub(w).max(self.pos1.0), self.pos1.1)
+        };
+        let (x2, y2) = self.pos2;
+        gfx.rect(x1, y1, x2, y2, self.pal[0]);
+        let mut buf = [0; 6];
+        let text = format_value(self.value, &mut buf);
+        let ty = y1 + (y2 - y1 + 1).saturating_sub(font.size().1) / 2;
+        let align = if self.vertical {
+            Align::Center(x2 - x1 + 1)
+        } else {
+            Align::Right(x2 - x1 + 1)
+        };
+        gfx.text_aligned(font, x1, ty, text, &self.pal, align);
+    }
+}
+
+/// A text label with a fixed box, which is cleared whenever the text changes.
+#[derive(Clone, Copy)]
+pub struct Label {
+    pub pos1: (u16, u16),
+    pub pos2: (u16, u16),
+    pub font: u8,
+    pub pal: Palette,
+    pub align: Align,
+}
+
+impl Label {
+    /// Check that the label's box is not empty.
+    pub fn is_valid(&self) -> bool {
+        self.pos1.0 <= self.pos2.0 && self.pos1.1 <= self.pos2.1
+    }
+
+    /// Clear the box and draw the new text into it.
+    pub fn set_text<Fb: FbImpl>(&self, gfx: &mut FrameBuffer<'_, Fb>, text: &[u8]) {
+        gfx.text_box(&FONTS[self.font as usize], self.pos1.0, self.pos1.1, self.pos2.0, self.pos2.1,
+                     text, &self.pal, self.align, true);
+    }
+}
+
+/// A touch button: a region of the graphics display that is reported to the
+/// host by ID when touched.
+///
+/// The button's contents are drawn by the host.  If a pressed palette is set,
+/// its colors are exchanged with the normal palette within the region while
+/// the button is highlighted.
+#[derive(Clone, Copy)]
+pub struct Button {
+    pub pos1: (u16, u16),
+    pub pos2: (u16, u16),
+    pub pal: Palette,
+    pub pressed: Option<Palette>,
+}
+
+impl Button {
+    /// Check that the button's region is not empty.
+    pub fn is_valid(&self) -> bool {
+        self.pos1.0 <= self.pos2.0 && self.pos1.1 <= self.pos2.1
+    }
+
+    pub fn contains(&self, (x, y): (u16, u16)) -> bool {
+        self.pos1.0 <= x && x <= self.pos2.0 && self.pos1.1 <= y && y <= self.pos2.1
+    }
+
+    /// Switch the highlight on or off, by mapping the colors from the normal
+    /// to the pressed palette or back.
+    pub fn set_highlight<Fb: FbImpl>(&self, gfx: &mut FrameBuffer<'_, Fb>, on: bool) {
+        if let Some(pressed) = self.pressed {
+            let (from, to) = if on { (&self.pal, &pressed) } else { (&pressed, &self.pal) };
+            gfx.map_colors(self.pos1.0, self.pos1.1, self.pos2.0, self.pos2.1, from, to);
+        }
+    }
+}
+
+/// A scrolling strip chart with up to `MAX_CHANNELS` lines.
+///
+/// The most recent sample is at the right edge.  Each sample pushed for a
+/// channel that is already at the right edge scrolls the chart by one pixel;
+/// samples for the other channels fill in the current column.  If axes are
+/// enabled, the minimum and maximum are shown on a vertical axis at the left,
+/// and a horizontal axis is drawn at the bottom.
+#[derive(Clone, Copy)]
+pub struct Chart {
+    pub pos1: (u16, u16),
+    pub pos2: (u16, u16),
+    pub min: i16,
+    pub max: i16,
+    pub axes: bool,
+    // if set, the range is extended when a sample falls outside of it
+    pub autoscale: bool,
+    pub channels: u8,
+    // line color of each channel
+    pub colors: [u8; MAX_CHANNELS],
+    // font and palette for background, axes and labels
+    pub font: u8,
+    pub pal: Palette,
+    pub samples: Samples,
+}
+
+/// Ring buffers of the samples of all channels of a chart.
+#[derive(Clone, Copy)]
+pub struct Samples {
+    data: [[i16; MAX_SAMPLES]; MAX_CHANNELS],
+    // number of samples pushed to each channel
+    count: [u32; MAX_CHANNELS],
+    // maximum of the counts, corresponds to the right edge of the chart
+    head: u32,
+}
+
+impl Default for Samples {
+    fn default() -> Self {
+        Self { data: [[0; MAX_SAMPLES]; MAX_CHANNELS], count: [0; MAX_CHANNELS], head: 0 }
+    }
+}
+
+impl Chart {
+    /// Check that the chart is on the display, and its plot area and range
+    /// are not empty.  The plot area must not be wider than the samples
+    /// retained for each channel.
+    pub fn is_valid(&self) -> bool {
+        let (p1, p2) = self.plot_rect();
+        self.pos1.0 <= self.pos2.0 && self.pos1.1 <= self.pos2.1 && self.min < self.max &&
+            self.pos2.0 < WIDTH && self.pos2.1 < HEIGHT &&
+            p1.0 <= p2.0 && p1.1 <= p2.1 && (p2.0 - p1.0 + 1) as usize <= MAX_SAMPLES &&
+            self.channels >= 1 && self.channels as usize <= MAX_CHANNELS
+    }
+
+    /// Draw the whole widget, including all retained samples.
+    pub fn draw<Fb: FbImpl>(&self, gfx: &mut FrameBuffer<'_, Fb>) {
+        gfx.rect(self.pos1.0, self.pos1.1, self.pos2.0, self.pos2.1, self.pal[0]);
+        if self.axes {
+            self.draw_axes(gfx);
+        }
+        let ((x1, _), (x2, _)) = self.plot_rect();
+        let width = (x2 - x1 + 1) as u32;
+        let first = self.samples.head.saturating_sub(width);
+        for ch in 0..self.channels as usize {
+            for n in first..self.samples.count[ch] {
+                self.draw_sample(gfx, ch, n, n > first);
+            }
+        }
+    }
+
+    /// Add a new sample to a channel, scrolling the chart if necessary.
+    pub fn push<Fb: FbImpl>(&mut self, gfx: &mut FrameBuffer<'_, Fb>, ch: usize, value: i16) {
+        let ((x1, y1), (x2, y2)) = self.plot_rect();
+        let width = (x2 - x1 + 1) as u32;
+        let n = self.samples.count[ch];
+        self.samples.data[ch][(n % width) as usize] = value;
+        self.samples.count[ch] = n + 1;
+        let scroll = n + 1 > self.samples.head;
+        if scroll {
+            self.samples.head = n + 1;
+        }
+        if self.autoscale && (value < self.min || value > self.max) {
+            self.rescale(value);
+            self.draw(gfx);
+            return;
+        }
+        if scroll {
+            if x2 > x1 {
+                gfx.copy_rect(x1 + 1, y1, x2, y2, x1, y1);
+            }
+            gfx.rect(x2, y1, x2, y2, self.pal[0]);
+        }
+        if self.samples.head - n <= width {
+            self.draw_sample(gfx, ch, n, n > 0 && self.samples.head - n < width);
+        }
+    }
+
+    /// Extend the range to include the value, with some headroom.
+    fn rescale(&mut self, value: i16) {
+        let headroom = (self.max as i32 - self.min as i32) / 8;
+        if value < self.min {
+            self.min = (value as i32 - headroom).max(i16::MIN as i32) as i16;
+        }
+        if value > self.max {
+            self.max = (value as i32 + headroom).min(i16::MAX as i32) as i16;
+        }
+    }
+
+    /// Return the (inclusive) rectangle of the plot area without axes.
+    fn plot_rect(&self) -> ((u16, u16), (u16, u16)) {
+        let (mut x1, y1) = self.pos1;
+        let (x2, mut y2) = self.pos2;
+        if self.axes {
+            x1 = x1.saturating_add(self.label_width() + 3);
+            y2 = y2.saturating_sub(2);
+        }
+        ((x1, y1), (x2, y2))
+    }
+
+    /// Width of the axis labels.  With autoscale, the labels can change, so
+    /// room for the longest possible number is reserved.
+    fn label_width(&self) -> u16 {
+        let (cw, _) = FONTS[self.font as usize].size();
+        let chars = if self.autoscale {
+            6
+        } else {
+            format_value(self.min, &mut [0; 6]).len().max(format_value(self.max, &mut [0; 6]).len())
+        };
+        cw * chars as u16
+    }
+
+    /// Return the y coordinate of a value in the plot area.
+    fn value_y(&self, value: i16) -> u16 {
+        let ((_, y1), (_, y2)) = self.plot_rect();
+        let value = value.clamp(self.min, self.max);
+        y2 - ((value as i32 - self.min as i32) * (y2 - y1) as i32 /
+              (self.max as i32 - self.min as i32)) as u16
+    }
+
+    /// Draw sample number `n` of a channel, connected to the previous one if
+    /// `connect` is set.
+    fn draw_sample<Fb: FbImpl>(&self, gfx: &mut FrameBuffer<'_, Fb>, ch: usize, n: u32, connect: bool) {
+        let ((x1, _), (x2, _)) = self.plot_rect();
+        let width = (x2 - x1 + 1) as u32;
+        let x = x2 - (self.samples.head - 1 - n) as u16;
+        let y = self.value_y(self.samples.data[ch][(n % width) as usize]);
+        if connect {
+            let y0 = self.value_y(self.samples.data[ch][((n - 1) % width) as usize]);
+            gfx.line(x - 1, y0, x, y, self.colors[ch]);
+        } else {
+            gfx.set_pixel(x, y, self.colors[ch]);
+        }
+    }
+
+    fn draw_axes<Fb: FbImpl>(&self, gfx: &mut FrameBuffer<'_, Fb>) {
+        let font = &FONTS[self.font as usize];
+        let ((px1, y1), (_, py2)) = self.plot_rect();
+        let (x1, _) = self.pos1;
+        let (x2, y2) = self.pos2;
+        gfx.line(px1 - 2, y1, px1 - 2, y2, self.pal[3]);
+        gfx.line(px1 - 2, y2, x2, y2, self.pal[3]);
+        let w = self.label_width();
+        let mut buf = [0; 6];
+        gfx.text_aligned(font, x1, y1, format_value(self.max, &mut buf), &self.pal, Align::Right(w));
+        let ty = (py2 + 1).saturating_sub(font.size().1).max(y1);
+        gfx.text_aligned(font, x1, ty, format_value(self.min, &mut buf), &self.pal, Align::Right(w));
+    }
+}
+
+/// Format a value as decimal number into the buffer.
+fn format_value(value: i16, buf: &mut [u8; 6]) -> &[u8] {
+    let mut pos = buf.len();
+    let mut rest = value.unsigned_abs();
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    if value < 0 {
+        pos -= 1;
+        buf[pos] = b'-';
+    }
+    &buf[pos..]
+}
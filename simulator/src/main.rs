@@ -5,6 +5,8 @@ use std::os::fd::{AsFd, OwnedFd};
 use crossbeam_channel::{unbounded, Receiver};
 use clap::Parser;
 use nix::{pty, fcntl::OFlag};
+use display::interface::Action;
+use display::overlay::{OVERLAY_WIDTH, OVERLAY_HEIGHT, OVERLAY_POS, OVERLAY_ALPHA};
 
 #[derive(Parser)]
 #[clap(author, version, about = "Box display simulator.")]
@@ -57,6 +59,7 @@ impl display::interface::TouchHandler for TouchHandler {
 }
 
 struct FbImpl<'a> {
+    width: u16,
     is_console: bool,
     disp_switch: &'a Cell<bool>,
 }
@@ -65,7 +68,7 @@ impl display::framebuf::FbImpl for FbImpl<'_> {
     fn fill_rect(&mut self, buf: &mut [u8], x1: u16, y1: u16, x2: u16, y2: u16, color: u8) {
         for y in y1..y2 {
             for x in x1..x2 {
-                buf[(x + y * display::WIDTH) as usize] = color;
+                buf[(x + y * self.width) as usize] = color;
             }
         }
     }
@@ -75,8 +78,8 @@ impl display::framebuf::FbImpl for FbImpl<'_> {
         let old = buf.to_vec();
         for iy in 0..ny {
             for ix in 0..nx {
-                buf[(x2 + ix + (y2 + iy) * display::WIDTH) as usize] =
-                    old[(x1 + ix + (y1 + iy) * display::WIDTH) as usize];
+                buf[(x2 + ix + (y2 + iy) * self.width) as usize] =
+                    old[(x1 + ix + (y1 + iy) * self.width) as usize];
             }
         }
     }
@@ -105,17 +108,20 @@ fn main() {
     }).expect("could not create window");
 
     // prepare color LUT (this is done in hardware on the STM)
-    let lut = display::console::get_lut_colors().map(|(r, g, b)| {
-        (r as u32) << 16 | (g as u32) << 8 | (b as u32)
-    }).collect::<Vec<_>>();
+    let rgb = |(r, g, b): (u8, u8, u8)| (r as u32) << 16 | (g as u32) << 8 | (b as u32);
+    let mut lut = display::console::get_lut_colors().map(rgb).collect::<Vec<_>>();
 
     const WIDTH: usize = display::WIDTH as usize;
     const HEIGHT: usize = display::HEIGHT as usize;
 
     // prepare framebuffers
     let mut fb_graphics = vec![0; WIDTH*HEIGHT];
+    let mut fb_graphics_back = vec![0; WIDTH*HEIGHT];
     let mut fb_console = vec![0; WIDTH*(HEIGHT + 8)];  // including extra row
 
+    let mut image_buf = vec![0; 16*1024];
+    let mut overlay_buf = vec![0; (OVERLAY_WIDTH*OVERLAY_HEIGHT) as usize];
+
     let mut fb_32bit = vec![0_u32; WIDTH*HEIGHT];  // actually displayed by minifb
 
     let console_active = Cell::new(true);
@@ -124,19 +130,33 @@ fn main() {
         display::framebuf::FrameBuffer::new(
             fb_console.as_mut_slice(),
             display::WIDTH, display::HEIGHT,
-            FbImpl { is_console: true, disp_switch: &console_active }),
+            FbImpl { width: display::WIDTH, is_console: true, disp_switch: &console_active }),
         WriteToHost { fd },
         (|_, _| ()) as fn(_, _)
     );
+    let mut graphics = display::framebuf::FrameBuffer::new(
+        fb_graphics.as_mut_slice(),
+        display::WIDTH, display::HEIGHT,
+        FbImpl { width: display::WIDTH, is_console: false, disp_switch: &console_active });
+    graphics.set_back_buffer(fb_graphics_back.as_mut_slice());
     let mut disp = display::interface::DisplayState::new(
-        display::framebuf::FrameBuffer::new(
-            fb_graphics.as_mut_slice(),
-            display::WIDTH, display::HEIGHT,
-            FbImpl { is_console: false, disp_switch: &console_active }),
+        graphics,
         console,
-        TouchHandler
+        TouchHandler,
+        display::image::ImageStore::new(image_buf.as_mut_slice()),
+        display::overlay::Overlay::new(
+            overlay_buf.as_mut_slice(),
+            FbImpl { width: OVERLAY_WIDTH, is_console: false, disp_switch: &console_active })
     );
 
+    // copy of the overlay window while it is shown (this is a separate
+    // hardware layer on the STM)
+    let mut overlay: Option<Vec<u8>> = None;
+    // backlight brightness (0-255), applied to the displayed colors
+    let mut brightness = 255;
+    // if true, the display is switched off by the screen saver
+    let mut blank = false;
+
     let mut mouse_was_down = false;
 
     let mut iteration = 0u32;
@@ -152,20 +172,77 @@ fn main() {
         // process input from remote tty
         let mut change = false;
         while let Ok(ch) = rx.try_recv() {
-            disp.process_byte(ch);
+            match disp.process_byte(ch) {
+                Action::ShowOverlay(buf) => overlay = Some(buf.to_vec()),
+                Action::HideOverlay => overlay = None,
+                Action::SetLut(start, colors) => {
+                    for (entry, c) in lut[start as usize..].iter_mut().zip(colors.chunks(3)) {
+                        *entry = rgb((c[0], c[1], c[2]));
+                    }
+                }
+                Action::ResetLut => {
+                    lut = display::console::get_lut_colors().map(rgb).collect();
+                }
+                _ => (),
+            }
             change = true;
         }
+        match disp.tick(50) {
+            Action::HideOverlay => {
+                overlay = None;
+                change = true;
+            }
+            Action::UpdateLut(updates) => {
+                for &(i, color) in updates {
+                    lut[i as usize] = rgb(color);
+                }
+                change = true;
+            }
+            Action::SetBacklight(level) => {
+                brightness = level as u32;
+                change = true;
+            }
+            Action::BlankDisplay(state) => {
+                blank = state;
+                change = true;
+            }
+            _ => (),
+        }
         iteration = iteration.wrapping_add(1);
         if change || iteration % 20 == 0 {
-            // check which framebuffer to display, and prepare the 32-bit buffer
+            // check which framebuffer to display, and prepare the 32-bit buffer;
+            // while a frame is drawn off-screen, the last frame stays visible
             let fb = if console_active.get() {
                 disp.console().buf()
             } else {
-                disp.graphics().buf()
+                disp.graphics().front_buf()
             };
             for (out, &color) in fb_32bit.iter_mut().zip(fb) {
                 *out = lut[color as usize];
             }
+            if let Some(overlay) = &overlay {
+                let (x0, y0) = (OVERLAY_POS.0 as usize, OVERLAY_POS.1 as usize);
+                let alpha = OVERLAY_ALPHA as u32;
+                for (i, &color) in overlay.iter().enumerate() {
+                    let (x, y) = (x0 + i % OVERLAY_WIDTH as usize, y0 + i / OVERLAY_WIDTH as usize);
+                    let out = &mut fb_32bit[x + y*WIDTH];
+                    let over = lut[color as usize];
+                    // blend each channel like the LTDC with constant alpha
+                    *out = [16, 8, 0].iter().map(|shift| {
+                        let (o, u) = ((over >> shift) & 0xff, (*out >> shift) & 0xff);
+                        ((o * alpha + u * (255 - alpha)) / 255) << shift
+                    }).sum();
+                }
+            }
+            if blank {
+                fb_32bit.fill(0);
+            } else if brightness < 255 {
+                for out in &mut fb_32bit {
+                    *out = [16, 8, 0].iter().map(|shift| {
+                        (((*out >> shift) & 0xff) * brightness / 255) << shift
+                    }).sum();
+                }
+            }
             win.update_with_buffer(&fb_32bit, WIDTH, HEIGHT)
                .expect("could not update window");
         } else {
@@ -177,6 +254,10 @@ fn main() {
             if let Some((x, y)) = win.get_mouse_pos(minifb::MouseMode::Discard) {
                 disp.process_touch((x as u16, y as u16));
             }
+        } else if !mouse_is_down && mouse_was_down {
+            if let Some((x, y)) = win.get_mouse_pos(minifb::MouseMode::Clamp) {
+                disp.process_release((x as u16, y as u16));
+            }
         }
         mouse_was_down = mouse_is_down;
         // aim for a framerate of 20Hz
@@ -1,15 +1,26 @@
 #!/usr/bin/env python3
 
 import sys
+import struct
+import binascii
 assert sys.version_info[0] == 3
 
 CMD_MODE_GRAPHICS = 0x20
 CMD_MODE_CONSOLE = 0x21
+CMD_BEGIN_FRAME = 0x22
+CMD_END_FRAME = 0x23
+CMD_OVERLAY = 0x24
 
 CMD_SET_POS = 0x30
 CMD_SET_FONT = 0x31
 CMD_SET_COLOR = 0x32
 CMD_SET_CLIP = 0x33
+CMD_SET_ALIGN = 0x34
+CMD_SET_CLUT = 0x35
+CMD_RESET_CLUT = 0x36
+CMD_ANIMATE_CLUT = 0x37
+CMD_SET_BACKLIGHT = 0x38
+CMD_SET_SAVER = 0x39
 
 CMD_CLEAR = 0x40
 CMD_LINES = 0x41
@@ -19,10 +30,41 @@ CMD_TEXT = 0x44
 CMD_COPYRECT = 0x45
 CMD_PLOT = 0x46
 CMD_PIXELS = 0x47
+CMD_CIRCLE = 0x48
+CMD_ELLIPSE = 0x49
+CMD_ARC = 0x4a
+CMD_POLYGON = 0x4b
+CMD_RECT_OUTLINE = 0x4c
+CMD_ROUND_RECT = 0x4d
+CMD_TEXT_SIZE = 0x4e
+CMD_TEXT_BOX = 0x4f
 
 CMD_TOUCH = 0x50
 CMD_TOUCH_MODE = 0x51
 CMD_TOUCH_CALIB = 0x52
+CMD_BUTTON = 0x53
+CMD_BUTTON_DEFINE = 0x54
+CMD_TOUCH_CALIBRATE = 0x55
+CMD_TOUCH_EVENT = 0x56
+
+CMD_IMAGE_DEFINE = 0x60
+CMD_IMAGE_DATA = 0x61
+CMD_PIXELS_1BPP = 0x62
+CMD_PIXELS_2BPP = 0x63
+CMD_PIXELS_4BPP = 0x64
+CMD_PIXELS_RLE = 0x65
+CMD_SCREENSHOT = 0x66
+
+CMD_ACK_MODE = 0x70
+CMD_ACK = 0x71
+CMD_CRC_MODE = 0x72
+
+CMD_BAR_DEFINE = 0x80
+CMD_BAR_VALUE = 0x81
+CMD_LABEL_DEFINE = 0x82
+CMD_LABEL_TEXT = 0x83
+CMD_CHART_DEFINE = 0x84
+CMD_CHART_PUSH = 0x85
 
 CMD_SAVE_ATTRS = 0xa0
 CMD_SAVE_ATTRS_MAX = 0xbf
@@ -36,6 +78,39 @@ CMD_SET_STARTUP = 0xf2
 CMD_VERSION = 0xf3
 CMD_RESET_APU = 0xf4
 CMD_APU_REINSTALL = 0xf5
+CMD_CAPABILITIES = 0xf6
+
+# commands whose data can exceed the display's command buffer, since it is
+# processed as it arrives
+STREAMABLE = {CMD_PIXELS, CMD_PIXELS_1BPP, CMD_PIXELS_2BPP, CMD_PIXELS_4BPP,
+              CMD_PIXELS_RLE, CMD_IMAGE_DATA}
+
+RAM_IMAGE = 0x80
+
+SEVERITY_INFO = 0
+SEVERITY_WARNING = 1
+SEVERITY_ERROR = 2
+
+TOUCH_MODE_TOGGLE = 0
+TOUCH_MODE_FORWARD = 1
+TOUCH_MODE_EVENTS = 2
+
+TOUCH_EVENTS = ['press', 'release', 'long_press', 'swipe']
+
+# optional commands in the capabilities reply, by bit number
+CAPABILITIES = ['frames', 'overlay', 'ack', 'crc', 'streaming', 'ram_images',
+                'screenshot', 'widgets', 'buttons', 'touch_events', 'clut',
+                'backlight', 'saver', 'saver_logo']
+
+ACK_OK = 0
+ACK_UNKNOWN = 1
+ACK_BAD_LENGTH = 2
+ACK_OUT_OF_RANGE = 3
+ACK_BAD_CRC = 4
+
+ALIGN_LEFT = 0
+ALIGN_CENTER = 1
+ALIGN_RIGHT = 2
 
 RESET_MAGIC = bytes([0xcb, 0xef, 0x20, 0x18])
 
@@ -67,6 +142,7 @@ class Display:
     def __init__(self, port):
         self.port = port
         self._record = None
+        self._crc = False
 
     def _pos(self, xy):
         x, y = xy
@@ -74,11 +150,18 @@ class Display:
 
     def send(self, cmd, argstr=b''):
         buf = bytearray(b'\x1b\x1b')
-        if len(argstr) > 254:
+        if len(argstr) > 0xfffe or (len(argstr) > 254 and cmd not in STREAMABLE):
             raise ValueError('command too long')
-        buf.append(len(argstr) + 1)
+        if len(argstr) > 254:
+            # extended framing with 16-bit length
+            buf.append(0)
+            buf.extend((len(argstr) + 1).to_bytes(2, 'big'))
+        else:
+            buf.append(len(argstr) + 1)
         buf.append(cmd)
         buf.extend(argstr)
+        if self._crc:
+            buf.extend(binascii.crc_hqx(buf[2:], 0xffff).to_bytes(2, 'big'))
         if self._record is None:
             self.port.write(buf)
         else:
@@ -96,12 +179,100 @@ class Display:
         assert rsp[:4] == b'\x1b\x1b\x05%c' % CMD_VERSION
         return list(rsp[4:])
 
-    def set_touch_mode(self, forward):
-        self.send(CMD_TOUCH_MODE, b'\x01' if forward else b'\x00')
+    def set_clut(self, start, colors):
+        """Set the color table from index start to the given (r, g, b)
+        colors."""
+        for i in range(0, len(colors), 84):
+            chunk = colors[i:i+84]
+            self.send(CMD_SET_CLUT, bytes([start + i] + [v for c in chunk for v in c]))
+
+    def reset_clut(self):
+        self.send(CMD_RESET_CLUT)
+
+    def set_backlight(self, level, dim_level=None, timeout=0, store=False):
+        """Set the backlight brightness (0-255).  If a timeout in seconds
+        is given, the backlight is dimmed to dim_level when there is no
+        touch or host output for this time.  If store is true, the settings
+        are stored and used after the next reset; this takes some time and
+        should not be done often."""
+        if dim_level is None:
+            dim_level = level
+        self.send(CMD_SET_BACKLIGHT, bytes([level, dim_level]) +
+                  struct.pack('>H', timeout) + bytes([int(store)]))
+
+    def set_saver(self, mode, timeout):
+        """Set the screen saver mode (0 = off, 1 = blank the display,
+        2 = show the moving logo) and the timeout in seconds without touch
+        or host output.  The display is restored on the next touch or
+        host output."""
+        self.send(CMD_SET_SAVER, bytes([mode]) + struct.pack('>H', timeout))
+
+    def animate_clut(self, index, period, colors):
+        """Cycle color table entry index through the (r, g, b) colors, with
+        the period in ms for a full cycle.  Without colors, the animation is
+        stopped."""
+        if not colors:
+            self.send(CMD_ANIMATE_CLUT, bytes([index]))
+        else:
+            self.send(CMD_ANIMATE_CLUT, bytes([index]) + struct.pack('>H', period) +
+                      bytes(v for c in colors for v in c))
+
+    def set_touch_mode(self, mode):
+        """Set one of the TOUCH_MODE_* constants; True and False select
+        forwarding and toggling."""
+        self.send(CMD_TOUCH_MODE, bytes([int(mode)]))
+
+    def set_ack_mode(self, on):
+        self.send(CMD_ACK_MODE, b'\x01' if on else b'\x00')
+
+    def read_ack(self):
+        """Read the acknowledgement for a command in ack mode.
+        Returns (sequence number, status)."""
+        rsp = self.port.read(6)
+        assert rsp[:4] == b'\x1b\x1b\x03%c' % CMD_ACK
+        return (rsp[4], rsp[5])
 
-    def set_touch_calib(self, xd, xo, yd=1, yo=0):
+    def set_crc_mode(self, on):
+        """Switch CRC-protected framing on or off.  Returns True if the
+        display confirmed the new mode.
+
+        Corrupted commands are dropped, and not acked in ack mode.  Only
+        streamed bulk data (see STREAMABLE) longer than 254 bytes is used
+        before its CRC can be checked; it is acked with ACK_BAD_CRC instead,
+        and should be sent again."""
+        self.send(CMD_CRC_MODE, b'\x01' if on else b'\x00')
+        rsp = self.port.read(5)
+        if rsp != b'\x1b\x1b\x02%c%c' % (CMD_CRC_MODE, on):
+            return False
+        self._crc = on
+        return True
+
+    def set_touch_calib(self, xd, xo, yd, yo):
+        """Set the touch calibration: the position on each axis is the raw
+        measurement divided by the divisor, minus the offset."""
         self.send(CMD_TOUCH_CALIB, bytes([xd, xo, yd, yo]))
 
+    def calibrate_touch(self):
+        """Start the interactive touch calibration on the display."""
+        self.send(CMD_TOUCH_CALIBRATE)
+
+    def screenshot(self, console=False, xy1=(0, 0), xy2=(479, 127)):
+        """Read back a rectangle of the graphics or console framebuffer.
+        Returns a list of rows of color indices."""
+        self.send(CMD_SCREENSHOT, bytes([console]) + self._pos(xy1) +
+                  self._pos(xy2))
+        x0, y0 = xy1
+        rows = [[0] * (xy2[0] - x0 + 1) for _ in range(xy2[1] - y0 + 1)]
+        while True:
+            hdr = self.port.read(4)
+            assert hdr[:2] == b'\x1b\x1b' and hdr[3] == CMD_SCREENSHOT
+            if hdr[2] == 1:
+                return rows
+            data = self.port.read(hdr[2] - 1)
+            x = ((data[0] & 1) << 8) | data[1]
+            y = data[0] >> 1
+            rows[y - y0][x - x0:x - x0 + len(data) - 2] = data[2:]
+
     def touch_detect(self):
         rsp = self.port.read(6)
         if not rsp:
@@ -109,6 +280,37 @@ class Display:
         assert rsp[:4] == b'\x1b\x1b\x03%c' % CMD_TOUCH
         return (rsp[4], rsp[5])
 
+    def define_button(self, i, xy1, xy2, pressed_colors=None):
+        """Define touch button i (0-31).  If pressed_colors are given, they
+        are exchanged with the current colors within the button while it is
+        highlighted."""
+        self.send(CMD_BUTTON_DEFINE, bytes([i]) + self._pos(xy1) +
+                  self._pos(xy2) + bytes(pressed_colors or []))
+
+    def remove_button(self, i):
+        self.send(CMD_BUTTON_DEFINE, bytes([i]))
+
+    def read_event(self):
+        """Read a touch reply.  Returns ('button', id), ('touch', (x, y)),
+        or in event mode (kind, (x, y), value) where kind is one of
+        TOUCH_EVENTS and value is the duration in ms for release and
+        long_press, and the horizontal distance for swipe.  A long_press
+        is sent while the screen is still touched, at the press position.
+        Returns None on timeout."""
+        hdr = self.port.read(4)
+        if not hdr:
+            return None
+        assert hdr[:2] == b'\x1b\x1b'
+        data = self.port.read(hdr[2] - 1)
+        if hdr[3] == CMD_BUTTON:
+            return ('button', data[0])
+        if hdr[3] == CMD_TOUCH_EVENT:
+            xy = (((data[1] & 1) << 8) | data[2], data[1] >> 1)
+            fmt = '>h' if TOUCH_EVENTS[data[0]] == 'swipe' else '>H'
+            return (TOUCH_EVENTS[data[0]], xy, struct.unpack(fmt, data[3:5])[0])
+        assert hdr[3] == CMD_TOUCH
+        return ('touch', (((data[0] & 1) << 8) | data[1], data[0] >> 1))
+
     def touch_detect_loop(self):
         while True:
             print('Touch:', *self.touch_detect())
@@ -127,6 +329,22 @@ class Display:
     def switch_graphics(self):
         self.send(CMD_MODE_GRAPHICS)
 
+    def begin_frame(self):
+        """Draw following commands off-screen until end_frame()."""
+        self.send(CMD_BEGIN_FRAME)
+
+    def end_frame(self):
+        self.send(CMD_END_FRAME)
+
+    def show_overlay(self, string, severity=SEVERITY_INFO, timeout=0):
+        """Show a notification over the display, hidden again after timeout
+        seconds (if nonzero)."""
+        self.send(CMD_OVERLAY, bytes([severity, timeout]) +
+                  string.encode('cp437'))
+
+    def hide_overlay(self):
+        self.send(CMD_OVERLAY)
+
     def set_pos(self, xy):
         self.send(CMD_SET_POS, self._pos(xy))
 
@@ -142,6 +360,44 @@ class Display:
     def reset_clip(self):
         self.send(CMD_SET_CLIP)
 
+    def set_align(self, align, width=0):
+        self.send(CMD_SET_ALIGN, bytes([align]) + width.to_bytes(2, 'big'))
+
+    def text_size(self, font, string):
+        self.send(CMD_TEXT_SIZE, bytes([font]) + string.encode('cp437'))
+        rsp = self.port.read(8)
+        assert rsp[:4] == b'\x1b\x1b\x05%c' % CMD_TEXT_SIZE
+        return (rsp[4] << 8 | rsp[5], rsp[6] << 8 | rsp[7])
+
+    def capabilities(self):
+        """Query the display properties.  Returns a dictionary with the
+        display size, console size in characters, maximum command length,
+        the set of supported optional commands (from CAPABILITIES), the
+        (width, height) of each font's characters, and the (width, height)
+        and default palette of each built-in image."""
+        self.send(CMD_CAPABILITIES)
+        hdr = self.port.read(4)
+        assert hdr[:2] == b'\x1b\x1b' and hdr[3] == CMD_CAPABILITIES
+        data = self.port.read(hdr[2] - 1)
+        width, height, cols, rows, max_len, caps = \
+            struct.unpack('>HHBBHI', data[:12])
+        nfonts = data[12]
+        fonts = [(data[13 + 2*i], data[14 + 2*i]) for i in range(nfonts)]
+        pos = 13 + 2*nfonts
+        images = []
+        for i in range(data[pos]):
+            w, h = struct.unpack('>HH', data[pos + 1 + 8*i:pos + 5 + 8*i])
+            images.append(((w, h), list(data[pos + 5 + 8*i:pos + 9 + 8*i])))
+        return {
+            'size': (width, height),
+            'console': (cols, rows),
+            'max_command_length': max_len,
+            'optional': {name for (i, name) in enumerate(CAPABILITIES)
+                         if caps & (1 << i)},
+            'fonts': fonts,
+            'images': images,
+        }
+
     def save_attrs(self, i):
         self.send(CMD_SAVE_ATTRS + i)
 
@@ -162,9 +418,22 @@ class Display:
         self.set_font(font)
         self.save_attrs(i)
 
+    def define_label(self, i, xy1, xy2, string=''):
+        """Define label i (0-31) with a fixed box, using the current font,
+        colors and alignment."""
+        self.send(CMD_LABEL_DEFINE, bytes([i]) + self._pos(xy1) +
+                  self._pos(xy2) + string.encode('cp437'))
+
+    def label_text(self, i, string):
+        self.send(CMD_LABEL_TEXT, bytes([i]) + string.encode('cp437'))
+
     def text(self, string):
         self.send(CMD_TEXT, string.encode('cp437'))
 
+    def text_box(self, xy1, xy2, string, ellipsis=True):
+        self.send(CMD_TEXT_BOX, self._pos(xy1) + self._pos(xy2) +
+                  bytes([ellipsis]) + string.encode('cp437'))
+
     def raw_text(self, sbytes):
         self.send(CMD_TEXT, sbytes)
 
@@ -174,6 +443,15 @@ class Display:
             buf.extend(self._pos(xy))
         self.send(CMD_LINES, buf)
 
+    def polygon(self, *coords):
+        buf = bytearray()
+        for xy in coords:
+            buf.extend(self._pos(xy))
+        self.send(CMD_POLYGON, buf)
+
+    def triangle(self, xy1, xy2, xy3):
+        self.polygon(xy1, xy2, xy3)
+
     def plot(self, x, y1, *ys):
         buf = bytearray(self._pos((x, y1)))
         for y in ys:
@@ -183,6 +461,14 @@ class Display:
     def rect(self, xy1, xy2):
         self.send(CMD_RECT, self._pos(xy1) + self._pos(xy2))
 
+    def rect_outline(self, xy1, xy2, radius=0, thickness=1):
+        self.send(CMD_RECT_OUTLINE, self._pos(xy1) + self._pos(xy2) +
+                  bytes([radius, thickness]))
+
+    def round_rect(self, xy1, xy2, radius):
+        self.send(CMD_ROUND_RECT, self._pos(xy1) + self._pos(xy2) +
+                  bytes([radius]))
+
     def clear(self, color):
         self.send(CMD_CLEAR, bytes([color]))
 
@@ -196,6 +482,78 @@ class Display:
         else:
             self.send(CMD_IMAGE, bytes([i]))
 
+    def upload_image(self, i, wh, data, colors):
+        """Upload 2bpp image data (4 pixels per byte, first pixel in the
+        lowest bits) to the RAM image number i (from RAM_IMAGE on)."""
+        self.send(CMD_IMAGE_DEFINE, bytes([i]) + self._pos(wh) + bytes(colors))
+        self.send(CMD_IMAGE_DATA, bytes([i]) + b'\x00\x00' + bytes(data))
+
     def pixels(self, xy1, wh, scale, colors):
         self.send(CMD_PIXELS, self._pos(xy1) + self._pos(wh) +
                   self._pos(scale) + bytes(colors))
+
+    def pixels_packed(self, xy1, wh, scale, bpp, values):
+        """Send 1bpp or 2bpp palette indices, or 4bpp basic colors."""
+        cmd = {1: CMD_PIXELS_1BPP, 2: CMD_PIXELS_2BPP, 4: CMD_PIXELS_4BPP}[bpp]
+        per_byte = 8 // bpp
+        data = bytearray()
+        for i in range(0, len(values), per_byte):
+            byte = 0
+            for j, v in enumerate(values[i:i + per_byte]):
+                byte |= v << (j * bpp)
+            data.append(byte)
+        self.send(cmd, self._pos(xy1) + self._pos(wh) + self._pos(scale) +
+                  bytes(data))
+
+    @staticmethod
+    def rle_encode(colors):
+        data = bytearray()
+        for color in colors:
+            if data and data[-1] == color and data[-2] < 255:
+                data[-2] += 1
+            else:
+                data.extend([1, color])
+        return bytes(data)
+
+    def pixels_rle(self, xy1, wh, scale, colors):
+        self.send(CMD_PIXELS_RLE, self._pos(xy1) + self._pos(wh) +
+                  self._pos(scale) + self.rle_encode(colors))
+
+    def define_bar(self, i, xy1, xy2, vmin, vmax, value, colors,
+                   vertical=False, label=False, ticks=0):
+        """Define and draw bar widget i (0-15).  colors are the colors of
+        the empty and filled parts; ticks and label use the current font
+        and colors."""
+        flags = vertical | (label << 1)
+        self.send(CMD_BAR_DEFINE, bytes([i]) + self._pos(xy1) + self._pos(xy2) +
+                  struct.pack('>hhh', vmin, vmax, value) +
+                  bytes([flags, ticks] + list(colors)))
+
+    def set_bar(self, i, value):
+        self.send(CMD_BAR_VALUE, bytes([i]) + struct.pack('>h', value))
+
+    def define_chart(self, i, xy1, xy2, vmin, vmax, colors, axes=True,
+                     autoscale=False):
+        """Define and draw strip chart i (0-1).  colors are the line colors
+        of the channels (1-4); background, axes and labels use the current
+        font and colors."""
+        flags = axes | (autoscale << 1)
+        self.send(CMD_CHART_DEFINE, bytes([i]) + self._pos(xy1) + self._pos(xy2) +
+                  struct.pack('>hh', vmin, vmax) + bytes([flags] + list(colors)))
+
+    def push_chart(self, i, channel, *values):
+        """Push a sample to a channel of chart i; further values are pushed
+        to the following channels."""
+        self.send(CMD_CHART_PUSH, bytes([i, channel]) +
+                  struct.pack('>%dh' % len(values), *values))
+
+    def circle(self, xy, r, fill=False):
+        self.send(CMD_CIRCLE, self._pos(xy) + bytes([r, fill]))
+
+    def ellipse(self, xy, rxy, fill=False):
+        self.send(CMD_ELLIPSE, self._pos(xy) + self._pos(rxy) + bytes([fill]))
+
+    def arc(self, xy, r, start, end, fill=False):
+        self.send(CMD_ARC, self._pos(xy) + bytes([r]) +
+                  start.to_bytes(2, 'big') + end.to_bytes(2, 'big') +
+                  bytes([fill]))
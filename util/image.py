@@ -31,12 +31,7 @@ for frame in range(getattr(img, 'n_frames', 1)):
     conv = img.convert('RGB').quantize(palette=p_img)
     frames.append([])
     for y in range(npy):
-        frames[-1].append(bytes(
-            conv.getpixel((x, y)) for x in range(min(npx, 240))
-        ))
-        frames[-1].append(bytes(
-            conv.getpixel((x, y)) for x in range(240, npx)
-        ))
+        frames[-1].append(bytes(conv.getpixel((x, y)) for x in range(npx)))
 
 d.clear(0)
 d.switch_graphics()
@@ -44,7 +39,9 @@ d.switch_graphics()
 while True:
     for frame in frames:
         for y in range(npy):
-            d.pixels((0, y*scale), (min(npx, 240), 1), scxy, frame[2*y])
-            if npx > 240:
-                d.pixels((240*scale, y*scale), (npx - 240, 1), scxy, frame[2*y+1])
+            row = frame[y]
+            if len(drawlib.Display.rle_encode(row)) < len(row):
+                d.pixels_rle((0, y*scale), (npx, 1), scxy, row)
+            else:
+                d.pixels((0, y*scale), (npx, 1), scxy, row)
         time.sleep(0.1)
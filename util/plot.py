@@ -14,30 +14,17 @@ d.clear(0)
 
 d.set_color([0, 8, 7, 15])
 d.set_font(0)
-d.pos_text((0, 1), "100")
-d.pos_text((0, 120), "  0")
-d.set_font(1)
-d.pos_text((1, 58), "T2")
-d.lines((20, 0), (20, 127))
-
-y1 = 50
+d.define_chart(0, (0, 0), (479, 127), 0, 100, [11])
 
 arr = [
-    int(40 * math.sin(0.02*x) + 60 + random.random() * math.sin(0.0437*x) * 20) for x in range(479)
+    int(40 * math.sin(0.02*x) + 60 + random.random() * math.sin(0.0437*x) * 20) for x in range(458)
 ]
 
-d.set_color([7]*4)
-d.lines((21, 64), (479, 64))
-d.set_color([11]*4)
-
-d.plot(21, *arr[21:250])
-d.plot(250, *arr[250:479])
+for y in arr:
+    d.push_chart(0, 0, y)
 
-y0 = arr[478]
+y0 = arr[-1]
 while 1:
     time.sleep(1)
-    d.copy_rect((22, 0), (479, 127), (21, 0))
-    y1 = max(min(y0 + int(random.random() * 10 - 5), 127), 0)
-    d.set_color([11]*4)
-    d.lines((477, y0), (478, y1))
-    y0 = y1
+    y0 = max(min(y0 + int(random.random() * 10 - 5), 100), 0)
+    d.push_chart(0, 0, y0)
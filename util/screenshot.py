@@ -0,0 +1,22 @@
+#!/usr/bin/env python3
+
+import sys
+
+import serial
+import PIL.Image
+
+import drawlib
+
+try:
+    _, port, outfile, *which = sys.argv
+except ValueError:
+    print('usage: screenshot.py port outfile.png [console]')
+    sys.exit(1)
+
+d = drawlib.Display(serial.Serial(port, baudrate=115200, timeout=5))
+rows = d.screenshot(console=(which == ['console']))
+
+img = PIL.Image.new('P', (len(rows[0]), len(rows)))
+img.putpalette(sum(map(list, drawlib.Colors.LUT), []))
+img.putdata([color for row in rows for color in row])
+img.save(outfile)